@@ -1,18 +1,83 @@
 use std::fs::{File, read_dir};
+use std::io::{Result, Write};
 
+const TARGET_PATH : &str = "../user/target/riscv64gc-unknown-none-elf/release/";
 
 fn main()
 {
-    let entitys = read_dir("../user/src/bin/").unwrap();
-
-    let entitys : Vec<_> = 
-    entitys.into_iter().map(|entity|{
-        let mut file_name = entity.unwrap().file_name().into_string().unwrap();
-        file_name.drain(file_name.find(".").unwrap()..file_name.len());
-        file_name
-    }).collect();
+    println!("cargo:rerun-if-changed=../user/src/");
+    println!("cargo:rerun-if-changed={}", TARGET_PATH);
+    write_app_table().unwrap();
+}
 
-    entitys.into_iter().for_each(|entity|{println!("{}", entity)});
-    
+/// names of the files in dir without their extension, sorted
+fn names_in(dir: &str, keep: impl Fn(&str) -> bool) -> Vec<String>
+{
+    let entitys = match read_dir(dir)
+    {
+        Ok(entitys) => entitys,
+        Err(_) => return Vec::new(),
+    };
+    let mut names : Vec<String> = entitys
+        .map(|entity| entity.unwrap().file_name().into_string().unwrap())
+        .filter(|name| keep(name))
+        .map(|mut file_name| {
+            if let Some(dot) = file_name.find(".")
+            {
+                file_name.drain(dot..file_name.len());
+            }
+            file_name
+        })
+        .collect();
+    names.sort();
+    names
 }
 
+/// src/app.S packs every app for the loader, as (name, elf) pairs after their count and
+/// bounds: the static apps, libuser.so for the ones that name it as interpreter and the
+/// apps the user Makefile linked against it, which get a _dyn suffix
+fn write_app_table() -> Result<()>
+{
+    let mut apps : Vec<(String, String)> = names_in("../user/src/bin/", |_| true)
+        .into_iter()
+        .map(|name| (name.clone(), format!("{}{}", TARGET_PATH, name)))
+        .collect();
+    apps.push((String::from("libuser.so"), format!("{}libuser.so", TARGET_PATH)));
+    let dynamic = format!("{}dynamic/", TARGET_PATH);
+    for name in names_in(&dynamic, |name| !name.contains("."))
+    {
+        apps.push((format!("{}_dyn", name), format!("{}{}", dynamic, name)));
+    }
+
+    let mut f = File::create("src/app.S")?;
+    writeln!(f, "    .align 3")?;
+    writeln!(f, "    .section .data")?;
+    writeln!(f, "    .global apps")?;
+    writeln!(f, "apps:")?;
+    writeln!(f, "    .quad {}", apps.len())?;
+    for i in 0..apps.len()
+    {
+        writeln!(f, "    .quad app{}_start", i)?;
+    }
+    writeln!(f, "    .quad app{}_end", apps.len() - 1)?;
+
+    writeln!(f)?;
+    writeln!(f, "    .global app_names")?;
+    writeln!(f, "app_names:")?;
+    for (name, _) in apps.iter()
+    {
+        writeln!(f, "    .string \"{}\"", name)?;
+    }
+
+    for (i, (_, path)) in apps.iter().enumerate()
+    {
+        writeln!(f)?;
+        writeln!(f, ".section .data")?;
+        writeln!(f, ".global app{}_start", i)?;
+        writeln!(f, ".align 3")?;
+        writeln!(f, "app{}_start:", i)?;
+        writeln!(f, "    .incbin \"{}\"", path)?;
+        writeln!(f, "app{}_end:", i)?;
+    }
+    Ok(())
+}
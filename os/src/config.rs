@@ -1,21 +1,55 @@
-pub const APP_START : usize = 0x80400000;
-pub const APP_MAXSIZE : usize = 0x20000;
 pub const APP_MAX_COUNT : usize = 3;
 pub const KERNEL_STACK_SIZE : usize = 4096;
-pub const USER_STACK_SIZE : usize = 4096;
+/// stack mapped at exec, it grows on faults up to USER_STACK_LIMIT
+pub const USER_STACK_SIZE : usize = 4096 * 4;
+pub const USER_STACK_LIMIT : usize = 4096 * 256;
+/// bytes the argument and environment strings of exec may take with their pointers,
+/// they go on the stack mapped at exec along with the auxiliary vector
+pub const ARG_MAX : usize = 4096 * 2;
 pub const CLOCK_FREQ : usize = 12500000;
+/// timer ticks between passes merging identical read-only pages, 0 for no passes
+pub const DEDUP_INTERVAL : usize = 100;
+/// refuse elfs with a segment, or a page shared by segments, both writable and executable
+pub const ELF_DENY_WX : bool = false;
+/// static part of the kernel heap, it grows from the frame allocator past that
 pub const HEAP_SIZE : usize = 4096 * 48;
+/// the heap grows by at least this many pages and by at most HEAP_GROW_MAX_PAGES,
+/// unless one allocation needs more
+pub const HEAP_GROW_PAGES : usize = 16;
+pub const HEAP_GROW_MAX_PAGES : usize = 256;
+/// ram assumed when the device tree has none to offer
+pub const MEM_START : usize = 0x80000000;
 pub const MEM_END : usize = 0x80800000;
-pub const MAX_VA : usize = 1 << 38;
+/// the first virtio-mmio slot of the qemu virt board, the swap disk goes there
+pub const VIRTIO0 : usize = 0x10001000;
+/// device registers identity mapped into the kernel space
+pub const MMIO : &[(usize, usize)] = &[(VIRTIO0, 0x1000)];
+/// user space is the lower half of the virtual address space
+pub const MAX_VA : usize = 1 << (VIRT_WIDTH - 1);
 pub const TRAMPOLINE : usize = MAX_VA - PAGE_SIZE;
 pub const TRAPFRAME : usize = TRAMPOLINE - PAGE_SIZE;
 pub const USERSTACK_TOP : usize = TRAPFRAME;
-pub const USERSTACK_BOTTOM : usize = USERSTACK_TOP - PAGE_SIZE;
+pub const USERSTACK_BOTTOM : usize = USERSTACK_TOP - USER_STACK_LIMIT;
+/// never mapped, a fault here is a stack overflow
+pub const USERSTACK_GUARD : usize = USERSTACK_BOTTOM - PAGE_SIZE;
+/// position independent executables are loaded at the first suitably aligned address past this
+pub const PIE_BASE : usize = 0x10000;
+/// the interpreter a program asks for is loaded past this, clear of the program and its heap
+pub const INTERP_BASE : usize = 0x8_0000_0000;
+pub const MMAP_BASE : usize = 0x10_0000_0000;
+pub const MMAP_TOP : usize = USERSTACK_GUARD;
 
 // mem stuffs
 pub const PAGE_BITS : usize = 12;
 pub const PAGE_SIZE : usize = 1<<PAGE_BITS;
 pub const PHY_WIDTH : usize = 56;
-pub const VIRT_WIDTH : usize = 39;
+/// levels of the page table walk, 4 (Sv48) with the sv48 feature and 3 (Sv39) without
+#[cfg(feature = "sv48")]
+pub const PAGE_LEVELS : usize = 4;
+#[cfg(not(feature = "sv48"))]
+pub const PAGE_LEVELS : usize = 3;
+/// the MODE field of satp, 8 for Sv39 and 9 for Sv48
+pub const SATP_MODE : usize = PAGE_LEVELS + 5;
+pub const VIRT_WIDTH : usize = PAGE_BITS + 9 * PAGE_LEVELS;
 pub const PPN_WIDTH : usize = PHY_WIDTH - PAGE_BITS;
 pub const VPN_WIDTH : usize = VIRT_WIDTH - PAGE_BITS;
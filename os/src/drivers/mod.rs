@@ -0,0 +1,3 @@
+mod virtio_blk;
+
+pub use virtio_blk::{VirtioBlk, SECTOR_SIZE};
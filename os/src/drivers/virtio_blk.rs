@@ -0,0 +1,274 @@
+use core::sync::atomic::{fence, Ordering};
+use crate::config::{PAGE_SIZE, PAGE_BITS};
+use crate::mm::{alloc_contiguous, ContiguousFrames, PhyAddr};
+
+pub const SECTOR_SIZE : usize = 512;
+
+const MAGIC : u32 = 0x7472_6976;
+const DEVICE_BLOCK : u32 = 2;
+
+/// virtio mmio register offsets
+const REG_MAGIC : usize = 0x000;
+const REG_VERSION : usize = 0x004;
+const REG_DEVICE_ID : usize = 0x008;
+const REG_DRIVER_FEATURES : usize = 0x020;
+const REG_DRIVER_FEATURES_SEL : usize = 0x024;
+const REG_GUEST_PAGE_SIZE : usize = 0x028;
+const REG_QUEUE_SEL : usize = 0x030;
+const REG_QUEUE_NUM_MAX : usize = 0x034;
+const REG_QUEUE_NUM : usize = 0x038;
+const REG_QUEUE_ALIGN : usize = 0x03c;
+const REG_QUEUE_PFN : usize = 0x040;
+const REG_QUEUE_READY : usize = 0x044;
+const REG_QUEUE_NOTIFY : usize = 0x050;
+const REG_STATUS : usize = 0x070;
+const REG_QUEUE_DESC : usize = 0x080;
+const REG_QUEUE_AVAIL : usize = 0x090;
+const REG_QUEUE_USED : usize = 0x0a0;
+const REG_CAPACITY : usize = 0x100;
+
+const STATUS_ACKNOWLEDGE : u32 = 1;
+const STATUS_DRIVER : u32 = 2;
+const STATUS_DRIVER_OK : u32 = 4;
+const STATUS_FEATURES_OK : u32 = 8;
+
+/// VIRTIO_F_VERSION_1 is feature bit 32, bit 0 of the second feature word
+const FEATURE_VERSION_1 : u32 = 1;
+
+const DESC_F_NEXT : u16 = 1;
+const DESC_F_WRITE : u16 = 2;
+const AVAIL_F_NO_INTERRUPT : u16 = 1;
+
+const BLK_T_IN : u32 = 0;
+const BLK_T_OUT : u32 = 1;
+const BLK_S_OK : u8 = 0;
+
+/// one request is a chain of header, data and status, so 3 descriptors are enough
+const QUEUE_SIZE : usize = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor
+{
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct AvailRing
+{
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElem
+{
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct UsedRing
+{
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+#[repr(C)]
+struct BlkRequest
+{
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// the legacy layout puts the used ring on the page after the descriptors
+/// and the available ring, the request header and status live on a third page
+const QUEUE_PAGES : usize = 3;
+
+/// a polled virtio block device on the mmio transport, version 1 (legacy) or 2
+pub struct VirtioBlk
+{
+    base: usize,
+    queue: ContiguousFrames,
+    last_used: u16,
+    capacity: usize,
+}
+
+impl VirtioBlk
+{
+    fn read_reg(&self, offset: usize) -> u32
+    {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32)
+    {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    fn write_reg64(&self, offset: usize, value: usize)
+    {
+        self.write_reg(offset, value as u32);
+        self.write_reg(offset + 4, (value >> 32) as u32);
+    }
+
+    fn queue_addr(&self) -> usize
+    {
+        usize::from(PhyAddr::from(self.queue.ppn()))
+    }
+
+    fn descs(&self) -> &'static mut [Descriptor; QUEUE_SIZE]
+    {
+        PhyAddr::from(self.queue_addr()).get_mut()
+    }
+
+    fn avail(&self) -> &'static mut AvailRing
+    {
+        PhyAddr::from(self.queue_addr() + core::mem::size_of::<[Descriptor; QUEUE_SIZE]>()).get_mut()
+    }
+
+    fn used(&self) -> &'static mut UsedRing
+    {
+        PhyAddr::from(self.queue_addr() + PAGE_SIZE).get_mut()
+    }
+
+    fn header_addr(&self) -> usize
+    {
+        self.queue_addr() + 2 * PAGE_SIZE
+    }
+
+    fn status_addr(&self) -> usize
+    {
+        self.header_addr() + core::mem::size_of::<BlkRequest>()
+    }
+
+    /// probe the mmio slot at base, None if there is no block device behind it
+    pub fn new(base: usize) -> Option<Self>
+    {
+        let queue = alloc_contiguous(QUEUE_PAGES)?;
+        queue.ppn().as_bytes().fill(0);
+        let mut blk = VirtioBlk
+        {
+            base,
+            queue,
+            last_used: 0,
+            capacity: 0,
+        };
+        if blk.read_reg(REG_MAGIC) != MAGIC || blk.read_reg(REG_DEVICE_ID) != DEVICE_BLOCK
+        {
+            return None;
+        }
+        let version = blk.read_reg(REG_VERSION);
+
+        blk.write_reg(REG_STATUS, 0);
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        blk.write_reg(REG_STATUS, status);
+
+        // no optional features, a modern device still wants VERSION_1 acknowledged
+        blk.write_reg(REG_DRIVER_FEATURES_SEL, 0);
+        blk.write_reg(REG_DRIVER_FEATURES, 0);
+        if version >= 2
+        {
+            blk.write_reg(REG_DRIVER_FEATURES_SEL, 1);
+            blk.write_reg(REG_DRIVER_FEATURES, FEATURE_VERSION_1);
+            status |= STATUS_FEATURES_OK;
+            blk.write_reg(REG_STATUS, status);
+            if blk.read_reg(REG_STATUS) & STATUS_FEATURES_OK == 0
+            {
+                return None;
+            }
+        }
+
+        blk.write_reg(REG_QUEUE_SEL, 0);
+        if (blk.read_reg(REG_QUEUE_NUM_MAX) as usize) < QUEUE_SIZE
+        {
+            return None;
+        }
+        blk.write_reg(REG_QUEUE_NUM, QUEUE_SIZE as u32);
+        let addr = blk.queue_addr();
+        if version == 1
+        {
+            blk.write_reg(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+            blk.write_reg(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+            blk.write_reg(REG_QUEUE_PFN, (addr >> PAGE_BITS) as u32);
+        }
+        else
+        {
+            blk.write_reg64(REG_QUEUE_DESC, addr);
+            blk.write_reg64(REG_QUEUE_AVAIL, addr + core::mem::size_of::<[Descriptor; QUEUE_SIZE]>());
+            blk.write_reg64(REG_QUEUE_USED, addr + PAGE_SIZE);
+            blk.write_reg(REG_QUEUE_READY, 1);
+        }
+        // we poll the used ring
+        blk.avail().flags = AVAIL_F_NO_INTERRUPT;
+
+        status |= STATUS_DRIVER_OK;
+        blk.write_reg(REG_STATUS, status);
+
+        let capacity = unsafe
+        {
+            ((blk.base + REG_CAPACITY) as *const u32).read_volatile() as usize
+            | (((blk.base + REG_CAPACITY + 4) as *const u32).read_volatile() as usize) << 32
+        };
+        blk.capacity = capacity;
+        Some(blk)
+    }
+
+    /// size of the disk in sectors
+    pub fn capacity(&self) -> usize
+    {
+        self.capacity
+    }
+
+    /// run one request over buf, which must be physically contiguous and identity mapped
+    fn request(&mut self, kind: u32, sector: usize, buf: usize, len: usize) -> bool
+    {
+        let header = PhyAddr::from(self.header_addr()).get_mut::<BlkRequest>();
+        *header = BlkRequest { kind, reserved: 0, sector: sector as u64 };
+        let status = PhyAddr::from(self.status_addr()).get_mut::<u8>();
+        *status = 0xff;
+
+        let data_flags = if kind == BLK_T_IN { DESC_F_WRITE } else { 0 };
+        let descs = self.descs();
+        descs[0] = Descriptor { addr: self.header_addr() as u64, len: core::mem::size_of::<BlkRequest>() as u32, flags: DESC_F_NEXT, next: 1 };
+        descs[1] = Descriptor { addr: buf as u64, len: len as u32, flags: data_flags | DESC_F_NEXT, next: 2 };
+        descs[2] = Descriptor { addr: self.status_addr() as u64, len: 1, flags: DESC_F_WRITE, next: 0 };
+
+        let avail = self.avail();
+        let idx = unsafe { core::ptr::read_volatile(&avail.idx) };
+        avail.ring[idx as usize % QUEUE_SIZE] = 0;
+        fence(Ordering::SeqCst);
+        unsafe { core::ptr::write_volatile(&mut avail.idx, idx.wrapping_add(1)) };
+        fence(Ordering::SeqCst);
+        self.write_reg(REG_QUEUE_NOTIFY, 0);
+
+        let used = self.used();
+        while unsafe { core::ptr::read_volatile(&used.idx) } == self.last_used
+        {
+            core::hint::spin_loop();
+        }
+        fence(Ordering::SeqCst);
+        self.last_used = self.last_used.wrapping_add(1);
+        unsafe { core::ptr::read_volatile(status) == BLK_S_OK }
+    }
+
+    /// read buf.len() / SECTOR_SIZE sectors starting at sector
+    pub fn read(&mut self, sector: usize, buf: &mut [u8]) -> bool
+    {
+        self.request(BLK_T_IN, sector, buf.as_ptr() as usize, buf.len())
+    }
+
+    pub fn write(&mut self, sector: usize, buf: &[u8]) -> bool
+    {
+        self.request(BLK_T_OUT, sector, buf.as_ptr() as usize, buf.len())
+    }
+}
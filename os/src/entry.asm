@@ -3,6 +3,7 @@
     .globl _start
 _start:
         la sp, boot_stack_top
+        # a0 = hart id, a1 = device tree, left for rust_main
         call rust_main
 
 
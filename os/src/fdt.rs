@@ -0,0 +1,187 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+const FDT_MAGIC : u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE : u32 = 1;
+const FDT_END_NODE : u32 = 2;
+const FDT_PROP : u32 = 3;
+const FDT_NOP : u32 = 4;
+/// deeper nodes are walked but can't be told apart by path
+const MAX_DEPTH : usize = 16;
+
+/// a flattened device tree blob as the firmware left it in memory, only read while
+/// the kernel runs on physical addresses or has the blob identity mapped
+pub struct Fdt
+{
+    base: usize,
+    total_size: usize,
+    struct_off: usize,
+    strings_off: usize,
+    rsvmap_off: usize,
+}
+
+fn be32(addr: usize) -> u32
+{
+    u32::from_be(unsafe { (addr as *const u32).read_unaligned() })
+}
+
+fn be64(addr: usize) -> u64
+{
+    u64::from_be(unsafe { (addr as *const u64).read_unaligned() })
+}
+
+/// the null terminated string at addr
+fn cstr(addr: usize) -> &'static str
+{
+    let mut len = 0;
+    while unsafe { ((addr + len) as *const u8).read() } != 0
+    {
+        len += 1;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    core::str::from_utf8(bytes).unwrap_or("")
+}
+
+fn align4(v: usize) -> usize
+{
+    (v + 3) & !3
+}
+
+/// (address, size) pairs of a reg property, each made of the given number of cells
+fn parse_reg(value: &[u8], address_cells: usize, size_cells: usize) -> Vec<(usize, usize)>
+{
+    let cell = |bytes: &[u8]| bytes.chunks_exact(4)
+        .fold(0usize, |acc, word| (acc << 32) | u32::from_be_bytes([word[0], word[1], word[2], word[3]]) as usize);
+    let entry = (address_cells + size_cells) * 4;
+    if entry == 0
+    {
+        return Vec::new();
+    }
+    value.chunks_exact(entry)
+        .map(|pair| (cell(&pair[..address_cells * 4]), cell(&pair[address_cells * 4..])))
+        .collect()
+}
+
+impl Fdt
+{
+    /// None if there is no valid blob at addr
+    pub fn new(addr: usize) -> Option<Self>
+    {
+        if addr == 0 || addr % 4 != 0 || be32(addr) != FDT_MAGIC
+        {
+            return None;
+        }
+        Some(Fdt
+        {
+            base: addr,
+            total_size: be32(addr + 4) as usize,
+            struct_off: be32(addr + 8) as usize,
+            strings_off: be32(addr + 12) as usize,
+            rsvmap_off: be32(addr + 16) as usize,
+        })
+    }
+
+    /// the bytes the blob takes, to keep them away from the frame allocator
+    pub fn range(&self) -> (usize, usize)
+    {
+        (self.base, self.base + self.total_size)
+    }
+
+    /// call f with the path of node names from the root (whose name is empty),
+    /// the property name and its value for every property of the tree
+    pub fn for_each_prop(&self, mut f: impl FnMut(&[&str], &str, &[u8]))
+    {
+        let mut path: [&str; MAX_DEPTH] = [""; MAX_DEPTH];
+        let mut depth = 0;
+        let mut cur = self.base + self.struct_off;
+        loop
+        {
+            let token = be32(cur);
+            cur += 4;
+            match token
+            {
+                FDT_BEGIN_NODE =>
+                {
+                    let name = cstr(cur);
+                    cur = align4(cur + name.len() + 1);
+                    if depth < MAX_DEPTH
+                    {
+                        path[depth] = name;
+                    }
+                    depth += 1;
+                },
+                FDT_END_NODE =>
+                {
+                    if depth == 0
+                    {
+                        return;
+                    }
+                    depth -= 1;
+                },
+                FDT_PROP =>
+                {
+                    let len = be32(cur) as usize;
+                    let name = cstr(self.base + self.strings_off + be32(cur + 4) as usize);
+                    let value = unsafe { core::slice::from_raw_parts((cur + 8) as *const u8, len) };
+                    cur = align4(cur + 8 + len);
+                    f(&path[..depth.min(MAX_DEPTH)], name, value);
+                },
+                FDT_NOP => {},
+                // FDT_END or garbage
+                _ => return,
+            }
+        }
+    }
+
+    /// (start, size) of every range in the memory nodes
+    pub fn memory_regions(&self) -> Vec<(usize, usize)>
+    {
+        let mut cells = (2, 1);
+        let mut regions = Vec::new();
+        self.for_each_prop(|path, name, value| {
+            match (path.len(), name)
+            {
+                (1, "#address-cells") => cells.0 = be32(value.as_ptr() as usize) as usize,
+                (1, "#size-cells") => cells.1 = be32(value.as_ptr() as usize) as usize,
+                (2, "reg") if path[1] == "memory" || path[1].starts_with("memory@") =>
+                    regions.extend(parse_reg(value, cells.0, cells.1)),
+                _ => {},
+            }
+        });
+        regions
+    }
+
+    /// (start, size) of the memory reservation block and of the children of
+    /// /reserved-memory that have a fixed place
+    pub fn reserved_regions(&self) -> Vec<(usize, usize)>
+    {
+        let mut regions = Vec::new();
+        let mut entry = self.base + self.rsvmap_off;
+        loop
+        {
+            let (start, size) = (be64(entry) as usize, be64(entry + 8) as usize);
+            if start == 0 && size == 0
+            {
+                break;
+            }
+            regions.push((start, size));
+            entry += 16;
+        }
+
+        let mut cells = (2, 1);
+        self.for_each_prop(|path, name, value| {
+            if path.len() < 2 || path[1] != "reserved-memory"
+            {
+                return;
+            }
+            match (path.len(), name)
+            {
+                (2, "#address-cells") => cells.0 = be32(value.as_ptr() as usize) as usize,
+                (2, "#size-cells") => cells.1 = be32(value.as_ptr() as usize) as usize,
+                (3, "reg") => regions.extend(parse_reg(value, cells.0, cells.1)),
+                _ => {},
+            }
+        });
+        regions
+    }
+}
@@ -59,23 +59,21 @@ pub fn get_app_count() -> usize
     }
 }
 
-pub fn get_index_by_name(target: &str) -> usize
+/// None if no app has that name
+pub fn get_index_by_name(target: &str) -> Option<usize>
 {
-    let res = APP_NAMES.iter().enumerate().find(|(_, name)| **name == target);
-    if let Some((index, _)) = res
-    {
-        index
-    }
-    else
-    {
-        panic!("can't find app {} in get_index_by_name", target);
-    }
+    APP_NAMES.iter().position(|name| *name == target)
+}
+
+pub fn get_app_name(i: usize) -> &'static str
+{
+    APP_NAMES[i]
 }
 
-pub fn get_app_by_name(target: &str) -> &'static [u8]
+/// None if no app has that name
+pub fn find_app_by_name(target: &str) -> Option<&'static [u8]>
 {
-    let index = get_index_by_name(target);
-    get_app(index)
+    get_index_by_name(target).map(get_app)
 }
 
 pub fn get_app(i:usize) -> &'static [u8]
@@ -17,14 +17,16 @@ mod config;
 mod loader;
 mod timer;
 mod mm;
+mod drivers;
+mod fdt;
 
 use crate::console::print;
 use trap::init as trap_init;
 use core::arch::asm;
 use core::arch::global_asm;
 use task::run;
+use task::init as task_init;
 use mm::init as mem_init;
-use mm::output_virpage_entry;
 use mm::test;
 use config::{TRAMPOLINE};
 extern "C"
@@ -49,13 +51,15 @@ fn clear_bss()
     });
 }
 
+/// the firmware passes the hart id and the address of the device tree
 #[no_mangle]
-pub fn rust_main() -> !
+pub fn rust_main(_hartid: usize, dtb: usize) -> !
 {
     clear_bss();
     trap_init();
     println!("hello world");
-    mem_init();
+    mem_init(dtb);
+    task_init();
     println!("back to world");
     
     run();
@@ -236,18 +236,24 @@ impl From<PhyAddr> for PhyPage
 /// VirPage methods and Interator
 impl VirPage
 {
-    pub fn indexs(&self) -> [usize; 3]
+    /// the index into the table of every level, root first
+    pub fn indexs(&self) -> [usize; PAGE_LEVELS]
     {
-        let mut res : [usize; 3] = [0; 3];
+        let mut res : [usize; PAGE_LEVELS] = [0; PAGE_LEVELS];
         let mut cur = self.0;
 
-        for i in (0..3).rev()
+        for i in (0..PAGE_LEVELS).rev()
         {
             res[i] = cur % 512;
             cur = cur >> 9;
         }
         res
     }
+
+    pub fn add(self, pages: usize) -> Self
+    {
+        VirPage::from(self.0 + pages)
+    }
 }
 
 pub trait StepOne
@@ -293,6 +299,11 @@ impl<T> Range<T>
         self.right
     }
 
+    pub fn contains(&self, v: T) -> bool
+    {
+        self.left <= v && v < self.right
+    }
+
 }
 
 impl<T> IntoIterator for Range<T>
@@ -0,0 +1,123 @@
+use core::arch::asm;
+use riscv::register::satp;
+use lazy_static::lazy_static;
+use crate::sync::up_safe_cell;
+use crate::config::SATP_MODE;
+
+/// generation of page tables that keep their asid forever (the kernel's)
+pub const PINNED : usize = usize::MAX;
+pub const KERNEL_ASID : usize = 0;
+const ASID_SHIFT : usize = 44;
+const ASID_MASK : usize = 0xffff;
+
+/// hands out hardware asids, when they run out the generation is bumped,
+/// the whole tlb is flushed and every address space has to pick a new one
+pub struct AsidAllocator
+{
+    generation: usize,
+    next: usize,
+    count: usize,
+}
+
+lazy_static!
+{
+    pub static ref ASID_ALLOCATOR: up_safe_cell<AsidAllocator> = unsafe
+    {
+        up_safe_cell::new(AsidAllocator { generation: 1, next: KERNEL_ASID + 1, count: 0 })
+    };
+}
+
+impl AsidAllocator
+{
+    pub fn generation(&self) -> usize
+    {
+        self.generation
+    }
+
+    pub fn supported(&self) -> bool
+    {
+        self.count > 1
+    }
+
+    /// (generation, asid) for an address space that has none in the current generation
+    pub fn alloc(&mut self) -> (usize, usize)
+    {
+        if !self.supported()
+        {
+            return (self.generation, KERNEL_ASID);
+        }
+        if self.next == self.count
+        {
+            self.generation += 1;
+            self.next = KERNEL_ASID + 1;
+            flush_all();
+        }
+        let asid = self.next;
+        self.next += 1;
+        (self.generation, asid)
+    }
+}
+
+/// find out how many asid bits the hart implements, must run with paging on
+pub fn init()
+{
+    let old = satp::read().bits();
+    unsafe
+    {
+        satp::write(old | (ASID_MASK << ASID_SHIFT));
+    }
+    let bits = (satp::read().bits() >> ASID_SHIFT) & ASID_MASK;
+    unsafe
+    {
+        satp::write(old);
+    }
+    // kernel pages touched while probing are cached under that asid, which a user space gets later
+    flush_asid(bits);
+    ASID_ALLOCATOR.access().count = bits + 1;
+    println!("asids: {}", bits + 1);
+}
+
+/// without asids every satp switch has to throw the tlb away
+pub fn flush_on_switch() -> bool
+{
+    !ASID_ALLOCATOR.access().supported()
+}
+
+pub fn satp_token(ppn: usize, asid: usize) -> usize
+{
+    SATP_MODE << 60 | (asid & ASID_MASK) << ASID_SHIFT | ppn
+}
+
+pub fn flush_all()
+{
+    unsafe
+    {
+        asm!("sfence.vma");
+    }
+}
+
+/// flush every non-global entry of asid
+pub fn flush_asid(asid: usize)
+{
+    unsafe
+    {
+        asm!("sfence.vma zero, {0}", in(reg) asid);
+    }
+}
+
+pub fn flush_page(va: usize, asid: usize)
+{
+    unsafe
+    {
+        asm!("sfence.vma {0}, {1}", in(reg) va, in(reg) asid);
+    }
+}
+
+/// flush va in every address space
+pub fn flush_page_global(va: usize)
+{
+    unsafe
+    {
+        asm!("sfence.vma {0}, zero", in(reg) va);
+    }
+}
@@ -0,0 +1,651 @@
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use xmas_elf::ElfFile;
+use xmas_elf::header::{self, Class, Data, Machine};
+use xmas_elf::program::{Type, ProgramHeader64};
+use lazy_static::lazy_static;
+use crate::config::{PAGE_SIZE, MAX_VA, USERSTACK_GUARD, ELF_DENY_WX, PIE_BASE, INTERP_BASE};
+use crate::loader::find_app_by_name;
+use crate::timer::get_time;
+use crate::sync::up_safe_cell;
+use super::frame_allocator::{alloc, FrameTracker};
+use super::memory_set::{MemorySet, MemArea, MapType, MemPermit, MemError};
+use super::{VirAddr, VirPage};
+
+const ENOENT : isize = 2;
+const E2BIG : isize = 7;
+const ENOEXEC : isize = 8;
+const ENOMEM : isize = 12;
+const ELF_MAGIC : [u8; 4] = [0x7f, 0x45, 0x4c, 0x46];
+
+const DT_NULL : u64 = 0;
+const DT_PLTRELSZ : u64 = 2;
+const DT_SYMTAB : u64 = 6;
+const DT_RELA : u64 = 7;
+const DT_RELASZ : u64 = 8;
+const DT_RELAENT : u64 = 9;
+const DT_SYMENT : u64 = 11;
+const DT_REL : u64 = 17;
+const DT_JMPREL : u64 = 23;
+const DYN_SIZE : usize = 16;
+const RELA_SIZE : usize = 24;
+const SYM_SIZE : usize = 24;
+const SHN_UNDEF : u16 = 0;
+const SHN_ABS : u16 = 0xfff1;
+const STB_WEAK : u8 = 2;
+
+const AT_NULL : usize = 0;
+const AT_PHDR : usize = 3;
+const AT_PHENT : usize = 4;
+const AT_PHNUM : usize = 5;
+const AT_PAGESZ : usize = 6;
+const AT_BASE : usize = 7;
+const AT_ENTRY : usize = 9;
+const AT_RANDOM : usize = 25;
+
+const R_RISCV_NONE : u32 = 0;
+const R_RISCV_64 : u32 = 2;
+const R_RISCV_RELATIVE : u32 = 3;
+const R_RISCV_JUMP_SLOT : u32 = 5;
+
+/// why an image can't be loaded, segments are named by their virtual address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElfError
+{
+    /// no app has the name exec was given
+    NotFound,
+    BadMagic,
+    /// shorter than its headers or its program header table
+    Truncated,
+    /// the program header table or its entries are not 8 byte aligned
+    BadHeader,
+    /// not a 64 bit image
+    WrongClass,
+    /// not little endian
+    WrongEndian,
+    /// not a risc-v image
+    WrongMachine,
+    /// neither an executable nor a position independent one
+    WrongType,
+    /// the segment wraps around or reaches past user space
+    OutsideUser(usize),
+    /// the segment reaches into the stack, the trap frame or the trampoline
+    OverlapsReserved(usize),
+    /// the file bytes of the segment run past the end of the image
+    PastFile(usize),
+    /// more file than memory bytes, or an alignment the segment doesn't honor
+    BadSegment(usize),
+    /// the segment shares bytes with the one before it
+    Overlap(usize),
+    /// writable and executable at once, only refused with ELF_DENY_WX
+    WritableExec(usize),
+    /// the dynamic table or a relocation table it points at is cut short or not in the file
+    BadDynamic,
+    /// a relocation of a kind the loader doesn't do, against an undefined symbol,
+    /// or outside the image, named by the address it patches
+    BadRelocation(usize),
+    /// no frames left to map or relocate the image
+    OutOfMemory,
+    /// the image or its stack would take the address space past its RLIMIT_RSS
+    OverLimit,
+    /// the arguments and environment don't fit on the initial stack
+    TooBig,
+    /// the PT_INTERP names no app the kernel has
+    NoInterpreter,
+    /// the interpreter is not position independent or wants an interpreter itself
+    BadInterpreter,
+}
+
+impl ElfError
+{
+    /// the negative errno exec returns for it
+    pub fn errno(self) -> isize
+    {
+        match self
+        {
+            ElfError::NotFound => -ENOENT,
+            ElfError::OverLimit => -ENOMEM,
+            ElfError::TooBig => -E2BIG,
+            _ => -ENOEXEC,
+        }
+    }
+}
+
+/// a PT_LOAD segment, data are its file bytes, which go at start
+struct Segment
+{
+    start: usize,
+    end: usize,
+    data: &'static [u8],
+    permit: MemPermit,
+}
+
+impl Segment
+{
+    fn first_page(&self) -> usize
+    {
+        self.start / PAGE_SIZE
+    }
+
+    /// one past the last page the segment touches
+    fn end_page(&self) -> usize
+    {
+        (self.end + PAGE_SIZE - 1) / PAGE_SIZE
+    }
+
+    fn touches(&self, page: usize) -> bool
+    {
+        self.first_page() <= page && page < self.end_page()
+    }
+
+    /// the file bytes that fall in [lo, hi), with how far past lo they start
+    fn data_in(&self, lo: usize, hi: usize) -> Option<(usize, &'static [u8])>
+    {
+        let start = self.start.max(lo);
+        let end = (self.start + self.data.len()).min(hi);
+        if start >= end
+        {
+            return None;
+        }
+        Some((start - lo, &self.data[start - self.start..end - self.start]))
+    }
+}
+
+lazy_static!
+{
+    /// the read-only pages of the interpreters, by image and page past the load bias,
+    /// built by the first exec that maps an image and kept for every later one
+    static ref IMAGE_FRAMES: up_safe_cell<BTreeMap<(usize, usize), Arc<FrameTracker>>> =
+    unsafe
+    {
+        up_safe_cell::new(BTreeMap::new())
+    };
+}
+
+/// the frames of the pages [first, end) of seg, a read-only segment of the image elf_data
+fn image_frames(elf_data: &'static [u8], seg: &Segment, first: usize, end: usize, bias: usize) -> Result<Vec<Arc<FrameTracker>>, ElfError>
+{
+    let mut cache = IMAGE_FRAMES.access();
+    let mut frames = Vec::new();
+    for page in first..end
+    {
+        let key = (elf_data.as_ptr() as usize, page - bias / PAGE_SIZE);
+        if let Some(frame) = cache.get(&key)
+        {
+            frames.push(Arc::clone(frame));
+            continue;
+        }
+        let frame = alloc().ok_or(ElfError::OutOfMemory)?;
+        let bytes = frame.ppn().as_bytes();
+        bytes.fill(0);
+        if let Some((offset, data)) = seg.data_in(page * PAGE_SIZE, (page + 1) * PAGE_SIZE)
+        {
+            bytes[offset..offset + data.len()].copy_from_slice(data);
+        }
+        let frame = Arc::new(frame);
+        cache.insert(key, Arc::clone(&frame));
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+/// the checks xmas_elf leaves to its caller, or panics on
+fn check_header(elf_data: &'static [u8]) -> Result<ElfFile<'static>, ElfError>
+{
+    if elf_data.len() < ELF_MAGIC.len() || elf_data[..ELF_MAGIC.len()] != ELF_MAGIC
+    {
+        return Err(ElfError::BadMagic);
+    }
+    let elf = ElfFile::new(elf_data).map_err(|_| ElfError::Truncated)?;
+    if elf.header.pt1.class() != Class::SixtyFour
+    {
+        return Err(ElfError::WrongClass);
+    }
+    if elf.header.pt1.data() != Data::LittleEndian
+    {
+        return Err(ElfError::WrongEndian);
+    }
+    if elf.header.pt2.machine().as_machine() != Machine::RISC_V
+    {
+        return Err(ElfError::WrongMachine);
+    }
+    if !matches!(elf.header.pt2.type_().as_type(), header::Type::Executable | header::Type::SharedObject)
+    {
+        return Err(ElfError::WrongType);
+    }
+    let pt2 = &elf.header.pt2;
+    let table_size = pt2.ph_count() as u64 * pt2.ph_entry_size() as u64;
+    let table_end = pt2.ph_offset().checked_add(table_size);
+    if pt2.ph_count() > 0 && ((pt2.ph_entry_size() as usize) < core::mem::size_of::<ProgramHeader64>()
+        || table_end.map_or(true, |end| end > elf_data.len() as u64))
+    {
+        return Err(ElfError::Truncated);
+    }
+    // xmas_elf reads the program headers in place and asserts they are aligned
+    let align = core::mem::align_of::<ProgramHeader64>();
+    if (elf_data.as_ptr() as usize).wrapping_add(pt2.ph_offset() as usize) % align != 0 || pt2.ph_entry_size() as usize % align != 0
+    {
+        return Err(ElfError::BadHeader);
+    }
+    Ok(elf)
+}
+
+fn is_pie(elf: &ElfFile) -> bool
+{
+    elf.header.pt2.type_().as_type() == header::Type::SharedObject
+}
+
+/// what gets added to every address of the image: 0 for an executable, which goes
+/// where it was linked, the lowest address past base that keeps the largest
+/// alignment its segments ask for for a position independent one
+fn load_bias(elf: &ElfFile, base: usize) -> Result<usize, ElfError>
+{
+    if !is_pie(elf)
+    {
+        return Ok(0);
+    }
+    let is_load = |ph: &xmas_elf::program::ProgramHeader| ph.get_type() == Ok(Type::Load) && ph.mem_size() > 0;
+    let align = elf.program_iter().filter(is_load).map(|ph| ph.align() as usize).fold(PAGE_SIZE, usize::max);
+    let low = elf.program_iter().filter(is_load).map(|ph| ph.virtual_addr() as usize).min().unwrap_or(0);
+    if !align.is_power_of_two()
+    {
+        return Err(ElfError::BadSegment(low));
+    }
+    let base = base.checked_add(align - 1).ok_or(ElfError::OutsideUser(low))? & !(align - 1);
+    Ok(base.saturating_sub(low & !(align - 1)))
+}
+
+fn segments(elf: &ElfFile, elf_data: &'static [u8], bias: usize) -> Result<Vec<Segment>, ElfError>
+{
+    let mut segments = Vec::new();
+    for ph in elf.program_iter()
+    {
+        if ph.get_type() != Ok(Type::Load) || ph.mem_size() == 0
+        {
+            continue;
+        }
+        let start = (ph.virtual_addr() as usize).checked_add(bias).ok_or(ElfError::OutsideUser(ph.virtual_addr() as usize))?;
+        let (file_size, mem_size) = (ph.file_size() as usize, ph.mem_size() as usize);
+        let (offset, align) = (ph.offset() as usize, ph.align() as usize);
+        let end = match start.checked_add(mem_size)
+        {
+            Some(end) if end <= MAX_VA => end,
+            _ => return Err(ElfError::OutsideUser(start)),
+        };
+        if end > USERSTACK_GUARD
+        {
+            return Err(ElfError::OverlapsReserved(start));
+        }
+        if offset.checked_add(file_size).map_or(true, |file_end| file_end > elf_data.len())
+        {
+            return Err(ElfError::PastFile(start));
+        }
+        if file_size > mem_size || !(align <= 1 || (align.is_power_of_two() && start % align == offset % align))
+        {
+            return Err(ElfError::BadSegment(start));
+        }
+        if ELF_DENY_WX && ph.flags().is_write() && ph.flags().is_execute()
+        {
+            return Err(ElfError::WritableExec(start));
+        }
+
+        let mut permit = MemPermit::U;
+        if ph.flags().is_read()
+        {
+            permit |= MemPermit::R;
+        }
+        if ph.flags().is_write()
+        {
+            permit |= MemPermit::W;
+        }
+        if ph.flags().is_execute()
+        {
+            permit |= MemPermit::X;
+        }
+        segments.push(Segment
+        {
+            start,
+            end,
+            data: &elf_data[offset..offset + file_size],
+            permit,
+        });
+    }
+    segments.sort_by_key(|seg| seg.start);
+    for pair in segments.windows(2)
+    {
+        if pair[0].end > pair[1].start
+        {
+            return Err(ElfError::Overlap(pair[1].start));
+        }
+    }
+    Ok(segments)
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64
+{
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// the len file bytes at vaddr of the image before it was moved by bias
+fn image_bytes(segments: &[Segment], bias: usize, vaddr: usize, len: usize) -> Option<&'static [u8]>
+{
+    let start = vaddr.checked_add(bias)?;
+    let end = start.checked_add(len)?;
+    let seg = segments.iter().find(|seg| seg.start <= start && end <= seg.start + seg.data.len())?;
+    Some(&seg.data[start - seg.start..end - seg.start])
+}
+
+/// the value of symbol index of the dynamic symbol table at symtab, undefined weak symbols are 0
+fn symbol_value(segments: &[Segment], bias: usize, symtab: usize, syment: usize, index: usize) -> Option<usize>
+{
+    let sym = image_bytes(segments, bias, symtab.checked_add(index.checked_mul(syment)?)?, SYM_SIZE)?;
+    let (info, shndx) = (sym[4], u16::from_le_bytes([sym[6], sym[7]]));
+    let value = read_u64(sym, 8) as usize;
+    match shndx
+    {
+        SHN_UNDEF if info >> 4 == STB_WEAK => Some(0),
+        SHN_UNDEF => None,
+        SHN_ABS => Some(value),
+        _ => Some(value.wrapping_add(bias)),
+    }
+}
+
+/// apply the RELA and JMPREL tables the dynamic segment points at to the image mapped in mem,
+/// there is no other object to look symbols up in so they all resolve within the image
+fn relocate(elf: &ElfFile, elf_data: &'static [u8], segments: &[Segment], bias: usize, mem: &mut MemorySet) -> Result<(), ElfError>
+{
+    let dynamic = match elf.program_iter().find(|ph| ph.get_type() == Ok(Type::Dynamic))
+    {
+        Some(ph) => ph,
+        None => return Ok(()),
+    };
+    let (offset, size) = (dynamic.offset() as usize, dynamic.file_size() as usize);
+    let table = offset.checked_add(size).and_then(|end| elf_data.get(offset..end)).ok_or(ElfError::BadDynamic)?;
+
+    let (mut rela, mut jmprel) = ((0, 0), (0, 0));
+    let (mut symtab, mut syment, mut relaent) = (0, SYM_SIZE, RELA_SIZE);
+    for entry in table.chunks_exact(DYN_SIZE)
+    {
+        let (tag, value) = (read_u64(entry, 0), read_u64(entry, 8) as usize);
+        match tag
+        {
+            DT_NULL => break,
+            DT_RELA => rela.0 = value,
+            DT_RELASZ => rela.1 = value,
+            DT_JMPREL => jmprel.0 = value,
+            DT_PLTRELSZ => jmprel.1 = value,
+            DT_SYMTAB => symtab = value,
+            DT_SYMENT => syment = value,
+            DT_RELAENT => relaent = value,
+            // risc-v only ever uses rela
+            DT_REL => return Err(ElfError::BadDynamic),
+            _ => {},
+        }
+    }
+    if relaent != RELA_SIZE || syment < SYM_SIZE
+    {
+        return Err(ElfError::BadDynamic);
+    }
+
+    for (addr, size) in [rela, jmprel]
+    {
+        if size == 0
+        {
+            continue;
+        }
+        let relas = image_bytes(segments, bias, addr, size).ok_or(ElfError::BadDynamic)?;
+        for entry in relas.chunks_exact(RELA_SIZE)
+        {
+            let (offset, info, addend) = (read_u64(entry, 0) as usize, read_u64(entry, 8), read_u64(entry, 16) as usize);
+            let target = offset.wrapping_add(bias);
+            let symbol = || symbol_value(segments, bias, symtab, syment, (info >> 32) as usize).ok_or(ElfError::BadRelocation(target));
+            let value = match info as u32
+            {
+                R_RISCV_NONE => continue,
+                R_RISCV_RELATIVE => bias.wrapping_add(addend),
+                R_RISCV_64 => symbol()?.wrapping_add(addend),
+                R_RISCV_JUMP_SLOT => symbol()?,
+                _ => return Err(ElfError::BadRelocation(target)),
+            };
+            // mem may hold another image by now, which this one has no business patching
+            let inside = target.checked_add(8).map_or(false, |end| segments.iter().any(|seg| seg.start <= target && end <= seg.end));
+            if !inside
+            {
+                return Err(ElfError::BadRelocation(target));
+            }
+            mem.write_bytes(target, &(value as u64).to_le_bytes()).map_err(|err| match err
+            {
+                MemError::BadAccess => ElfError::BadRelocation(target),
+                MemError::OutOfMemory => ElfError::OutOfMemory,
+                MemError::OverLimit => ElfError::OverLimit,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// the app named by the PT_INTERP of elf, looked up by the last part of its path
+fn interpreter(elf: &ElfFile, elf_data: &'static [u8]) -> Result<Option<&'static [u8]>, ElfError>
+{
+    let ph = match elf.program_iter().find(|ph| ph.get_type() == Ok(Type::Interp))
+    {
+        Some(ph) => ph,
+        None => return Ok(None),
+    };
+    let (offset, size) = (ph.offset() as usize, ph.file_size() as usize);
+    let path = offset.checked_add(size).and_then(|end| elf_data.get(offset..end)).ok_or(ElfError::NoInterpreter)?;
+    let path = path.split(|b| *b == 0).next().unwrap_or(&[]);
+    let name = path.rsplit(|b| *b == b'/').next().unwrap_or(&[]);
+    let name = core::str::from_utf8(name).map_err(|_| ElfError::NoInterpreter)?;
+    find_app_by_name(name).map(Some).ok_or(ElfError::NoInterpreter)
+}
+
+/// where the program headers of elf are once it is mapped, if any segment holds them
+fn phdr_addr(elf: &ElfFile, bias: usize) -> Option<usize>
+{
+    if let Some(ph) = elf.program_iter().find(|ph| ph.get_type() == Ok(Type::Phdr))
+    {
+        return Some((ph.virtual_addr() as usize).wrapping_add(bias));
+    }
+    let phoff = elf.header.pt2.ph_offset();
+    elf.program_iter()
+        .find(|ph| ph.get_type() == Ok(Type::Load) && ph.offset() <= phoff && phoff < ph.offset() + ph.file_size())
+        .map(|ph| ((ph.virtual_addr() + phoff - ph.offset()) as usize).wrapping_add(bias))
+}
+
+/// every byte of a segment lands at its virtual address: the pages a segment has to
+/// itself are a lazy area, a page shared by several segments is a framed page with
+/// the union of their permissions and the bytes of each, anything past the file bytes is zero,
+/// with share_text the pages a read-only segment has to itself come from IMAGE_FRAMES instead,
+/// nothing is mapped unless the whole image checks out, returns where the image ends
+fn map_image(mem: &mut MemorySet, elf: &ElfFile, elf_data: &'static [u8], bias: usize, share_text: bool) -> Result<usize, ElfError>
+{
+    let segments = segments(elf, elf_data, bias)?;
+
+    // only the first and last page of a segment can be shared, its bytes cover the rest
+    let mut shared: Vec<usize> = segments.iter()
+        .flat_map(|seg| [seg.first_page(), seg.end_page() - 1])
+        .filter(|page| segments.iter().filter(|seg| seg.touches(*page)).count() > 1)
+        .collect();
+    shared.sort();
+    shared.dedup();
+    let permit_of = |page: usize| segments.iter()
+        .filter(|seg| seg.touches(page))
+        .fold(MemPermit::U, |permit, seg| permit | seg.permit);
+    if let Some(page) = shared.iter().find(|page| ELF_DENY_WX && permit_of(**page).contains(MemPermit::W | MemPermit::X))
+    {
+        return Err(ElfError::WritableExec(page * PAGE_SIZE));
+    }
+
+    for page in shared.iter()
+    {
+        let (lo, hi) = (page * PAGE_SIZE, (page + 1) * PAGE_SIZE);
+        let pieces: Vec<(usize, &[u8])> = segments.iter()
+            .filter(|seg| seg.touches(*page))
+            .filter_map(|seg| seg.data_in(lo, hi))
+            .collect();
+        let area = MemArea::new(VirAddr::from(lo), VirAddr::from(hi), permit_of(*page), MapType::Framed);
+        mem.push_framed_area(area, &pieces).map_err(|_| ElfError::OutOfMemory)?;
+    }
+
+    // the lazy pages of read-only segments all come from the same image, so the
+    // dedup pass ends up sharing them between the processes that map it
+    for seg in segments.iter()
+    {
+        let mut first = seg.first_page();
+        let mut end = seg.end_page();
+        if shared.contains(&first)
+        {
+            first += 1;
+        }
+        if end > first && shared.contains(&(end - 1))
+        {
+            end -= 1;
+        }
+        if first >= end
+        {
+            continue;
+        }
+        if share_text && !seg.permit.contains(MemPermit::W)
+        {
+            let frames = image_frames(elf_data, seg, first, end, bias)?;
+            mem.map_frames(VirPage::from(first), &frames, seg.permit).map_err(|_| ElfError::OutOfMemory)?;
+            continue;
+        }
+        let (lo, hi) = (first * PAGE_SIZE, end * PAGE_SIZE);
+        let mut area = MemArea::new(VirAddr::from(lo), VirAddr::from(hi), seg.permit, MapType::Lazy);
+        match seg.data_in(lo, hi)
+        {
+            Some((offset, data)) =>
+            {
+                area.data_offset = offset;
+                mem.push_area(area, Some(data));
+            },
+            None => mem.push_area(area, None),
+        }
+    }
+
+    if is_pie(elf) && interpreter(elf, elf_data)?.is_none()
+    {
+        relocate(elf, elf_data, &segments, bias, mem)?;
+    }
+    Ok(segments.iter().map(|seg| seg.end).max().unwrap_or(0))
+}
+
+/// map the program, and the interpreter its PT_INTERP names past INTERP_BASE, the entry
+/// point is the one of the interpreter if there is one, which gets to relocate the program,
+/// a position independent program without one is moved by load_bias and relocated here,
+/// also returns the (type, value) pairs of the auxiliary vector for init_stack
+pub fn to_prog(elf_data: &'static [u8]) -> Result<(usize, MemorySet, Vec<(usize, usize)>), ElfError>
+{
+    let elf = check_header(elf_data)?;
+    let bias = load_bias(&elf, PIE_BASE)?;
+    let interp = interpreter(&elf, elf_data)?;
+
+    let mut res = MemorySet::try_new().ok_or(ElfError::OutOfMemory)?;
+    res.map_trampoline().map_err(|_| ElfError::OutOfMemory)?;
+    let end = map_image(&mut res, &elf, elf_data, bias, false)?;
+    let entry = (elf.header.pt2.entry_point() as usize).wrapping_add(bias);
+
+    let mut auxv = Vec::new();
+    if let Some(phdr) = phdr_addr(&elf, bias)
+    {
+        auxv.push((AT_PHDR, phdr));
+    }
+    auxv.push((AT_PHENT, elf.header.pt2.ph_entry_size() as usize));
+    auxv.push((AT_PHNUM, elf.header.pt2.ph_count() as usize));
+    auxv.push((AT_PAGESZ, PAGE_SIZE));
+    auxv.push((AT_ENTRY, entry));
+
+    let mut start = entry;
+    match interp
+    {
+        Some(interp_data) =>
+        {
+            let interp = check_header(interp_data)?;
+            if !is_pie(&interp) || interpreter(&interp, interp_data)?.is_some()
+            {
+                return Err(ElfError::BadInterpreter);
+            }
+            let interp_bias = load_bias(&interp, INTERP_BASE)?;
+            // every dynamic app maps the same interpreter, so its text is only built once
+            map_image(&mut res, &interp, interp_data, interp_bias, true)?;
+            start = (interp.header.pt2.entry_point() as usize).wrapping_add(interp_bias);
+            auxv.push((AT_BASE, interp_bias));
+        },
+        None => auxv.push((AT_BASE, 0)),
+    }
+
+    res.brk_start = usize::from(VirAddr::from(end).ceil());
+    res.brk = res.brk_start;
+    Ok((start, res, auxv))
+}
+
+/// 16 bytes for AT_RANDOM, there is no entropy source so they only differ between execs
+fn random_bytes() -> [u8; 16]
+{
+    static SEED: AtomicUsize = AtomicUsize::new(0);
+    let mut state = get_time() ^ SEED.fetch_add(0x9e3779b97f4a7c15, Ordering::Relaxed);
+    let mut next = || {
+        // splitmix64
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&next().to_le_bytes());
+    bytes[8..].copy_from_slice(&next().to_le_bytes());
+    bytes
+}
+
+/// build the System V initial stack below top: the argument and environment strings and the
+/// AT_RANDOM bytes go at the top, under them the stack pointer, 16 byte aligned, points at
+/// argc, then the argv and envp arrays, each null terminated, then the auxiliary vector
+pub fn init_stack(mem: &mut MemorySet, top: usize, args: &[String], envs: &[String], auxv: &[(usize, usize)]) -> Result<usize, ElfError>
+{
+    build_stack(mem, top, args, envs, auxv).map_err(|err| match err
+    {
+        // whatever doesn't fit runs off the stack area
+        MemError::BadAccess => ElfError::TooBig,
+        MemError::OutOfMemory => ElfError::OutOfMemory,
+        MemError::OverLimit => ElfError::OverLimit,
+    })
+}
+
+fn build_stack(mem: &mut MemorySet, top: usize, args: &[String], envs: &[String], auxv: &[(usize, usize)]) -> Result<usize, MemError>
+{
+    let mut cur = top - 16;
+    mem.write_bytes(cur, &random_bytes())?;
+
+    let mut push_str = |string: &String| -> Result<usize, MemError> {
+        cur -= string.len() + 1;
+        mem.write_bytes(cur, string.as_bytes())?;
+        mem.write_bytes(cur + string.len(), &[0])?;
+        Ok(cur)
+    };
+    let arg_ptrs = args.iter().map(&mut push_str).collect::<Result<Vec<usize>, MemError>>()?;
+    let env_ptrs = envs.iter().map(&mut push_str).collect::<Result<Vec<usize>, MemError>>()?;
+    let random = top - 16;
+
+    let mut words: Vec<usize> = Vec::new();
+    words.push(args.len());
+    words.extend(arg_ptrs.iter());
+    words.push(0);
+    words.extend(env_ptrs.iter());
+    words.push(0);
+    for (key, value) in auxv.iter().chain([(AT_RANDOM, random), (AT_NULL, 0)].iter())
+    {
+        words.extend([*key, *value]);
+    }
+    let sp = (cur - words.len() * core::mem::size_of::<usize>()) & !15;
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    mem.write_bytes(sp, &bytes)?;
+    Ok(sp)
+}
@@ -1,15 +1,9 @@
 extern crate alloc;
-use crate::config::{MEM_END, PAGE_SIZE, PAGE_BITS};
+use crate::config::{PAGE_SIZE, PAGE_BITS};
 use crate::sync::up_safe_cell;
 use crate::mm::addr::*;
-use alloc::vec::Vec;
 use lazy_static::lazy_static;
 
-extern "C"
-{
-    fn ekernel();
-}
-
 pub struct FrameTracker
 {
     pub ppn: PhyPage,
@@ -35,7 +29,50 @@ impl Drop for FrameTracker
 {
     fn drop(&mut self)
     {
-       dealloc(self.ppn); 
+       dealloc(self.ppn);
+    }
+}
+
+/// count physically contiguous frames starting at ppn, aligned to count
+pub struct ContiguousFrames
+{
+    pub ppn: PhyPage,
+    pub count: usize,
+}
+
+impl ContiguousFrames
+{
+    pub fn ppn(&self) -> PhyPage
+    {
+        self.ppn
+    }
+
+    pub fn count(&self) -> usize
+    {
+        self.count
+    }
+}
+
+impl Drop for ContiguousFrames
+{
+    fn drop(&mut self)
+    {
+        FRAME_ALLOCATOR.access().dealloc_contiguous(self.ppn, self.count);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameStats
+{
+    pub total: usize,
+    pub free: usize,
+}
+
+impl FrameStats
+{
+    pub fn used(&self) -> usize
+    {
+        self.total - self.free
     }
 }
 
@@ -44,70 +81,282 @@ trait FrameAllocator
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhyPage>;
     fn dealloc(&mut self, pg: PhyPage);
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhyPage>;
+    fn dealloc_contiguous(&mut self, pg: PhyPage, count: usize);
+    fn stats(&self) -> FrameStats;
+}
+
+/// blocks go up to 2^(MAX_ORDER-1) frames
+const MAX_ORDER : usize = 11;
+const NIL : usize = usize::MAX;
+
+/// per frame state byte, only the head frame of a block carries a tag
+const META_FREE : u8 = 0x80;
+const META_USED : u8 = 0x40;
+/// set on a used frame the slab allocator carved into objects
+const META_SLAB : u8 = 0x20;
+
+/// links of a free block, kept inside the block's head frame
+struct FreeNode
+{
+    prev: usize,
+    next: usize,
 }
 
-pub struct Allocator
+/// buddy allocator over the frames [base, end), its state bytes live in
+/// frames carved from the start of the range so it never touches the heap,
+/// frames in the holes between the ranges it was given are never free
+pub struct BuddyAllocator
 {
-    begin : usize,
+    base : usize,
     end : usize,
-    frames : Vec<usize>,
+    meta : &'static mut [u8],
+    free_lists : [usize; MAX_ORDER],
+    free : usize,
+    total : usize,
 }
 
-impl FrameAllocator for Allocator
+impl BuddyAllocator
 {
-    fn new() -> Self
+    fn order_of(count: usize) -> usize
     {
-        let mut start = ((ekernel as usize + PAGE_SIZE - 1) & !(PAGE_SIZE - 1));
-        let end = MEM_END & !(PAGE_SIZE - 1);
-        let mut v = Vec::new();
+        count.next_power_of_two().trailing_zeros() as usize
+    }
 
-        Self 
+    fn node(ppn: usize) -> &'static mut FreeNode
+    {
+        PhyAddr::from(ppn << PAGE_BITS).get_mut()
+    }
+
+    fn in_range(&self, ppn: usize) -> bool
+    {
+        self.base <= ppn && ppn < self.end
+    }
+
+    fn push_free(&mut self, ppn: usize, order: usize)
+    {
+        let head = self.free_lists[order];
+        *Self::node(ppn) = FreeNode { prev: NIL, next: head };
+        if head != NIL
         {
-            begin : start,
-            end : end,
-            frames: v,
+            Self::node(head).prev = ppn;
         }
+        self.free_lists[order] = ppn;
+        self.meta[ppn - self.base] = META_FREE | order as u8;
     }
 
-    fn alloc(&mut self) -> Option<PhyPage>
+    fn remove_free(&mut self, ppn: usize, order: usize)
+    {
+        let FreeNode { prev, next } = *Self::node(ppn);
+        if prev != NIL
+        {
+            Self::node(prev).next = next;
+        }
+        else
+        {
+            self.free_lists[order] = next;
+        }
+        if next != NIL
+        {
+            Self::node(next).prev = prev;
+        }
+        self.meta[ppn - self.base] = 0;
+    }
+
+    /// hand the sorted, page aligned byte ranges to the allocator, the state bytes for
+    /// every frame from the first range to the last go at the start of the first range
+    /// that has room for them
+    fn init_ranges(&mut self, ranges: &[(usize, usize)]) -> Result<(), FrameInitError>
     {
-        self.frames.pop().map(|which_page|{PhyPage::from(which_page)})
+        let (first, last) = match (ranges.first(), ranges.last())
+        {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(FrameInitError::NoRam),
+        };
+        let begin = first.0 >> PAGE_BITS;
+        let end = last.1 >> PAGE_BITS;
+        let meta_frames = ((end - begin) + PAGE_SIZE - 1) / PAGE_SIZE;
+        let meta_start = ranges.iter()
+            .map(|(start, stop)| (start >> PAGE_BITS, stop >> PAGE_BITS))
+            .find(|(start, stop)| stop - start >= meta_frames)
+            .map(|(start, _)| start)
+            .ok_or(FrameInitError::NoRoomForState)?;
+        self.meta = unsafe
+        {
+            core::slice::from_raw_parts_mut((meta_start << PAGE_BITS) as *mut u8, end - begin)
+        };
+        self.meta.fill(0);
+        self.base = begin;
+        self.end = end;
+        self.free_lists = [NIL; MAX_ORDER];
+        self.free = 0;
+
+        for (start, stop) in ranges
+        {
+            let start = start >> PAGE_BITS;
+            // the frames holding the state are never free
+            let start = if start == meta_start { start + meta_frames } else { start };
+            self.free_frames(start, stop >> PAGE_BITS);
+        }
+        self.total = self.free;
+        Ok(())
     }
 
-    fn dealloc(&mut self, pg: PhyPage) 
+    /// push [ppn, stop) as the largest aligned blocks that fit
+    fn free_frames(&mut self, mut ppn: usize, stop: usize)
     {
-        if usize::from(pg) < self.begin || usize::from(pg) >= self.end
+        while ppn < stop
+        {
+            let mut order = 0;
+            while order + 1 < MAX_ORDER
+                && ppn % (1 << (order + 1)) == 0
+                && ppn + (1 << (order + 1)) <= stop
+            {
+                order += 1;
+            }
+            self.push_free(ppn, order);
+            self.free += 1 << order;
+            ppn += 1 << order;
+        }
+    }
+
+    fn alloc_order(&mut self, order: usize) -> Option<usize>
+    {
+        let mut cur = (order..MAX_ORDER).find(|k| self.free_lists[*k] != NIL)?;
+        let ppn = self.free_lists[cur];
+        self.remove_free(ppn, cur);
+        while cur > order
+        {
+            cur -= 1;
+            self.push_free(ppn + (1 << cur), cur);
+        }
+        self.meta[ppn - self.base] = META_USED | order as u8;
+        self.free -= 1 << order;
+        Some(ppn)
+    }
+
+    fn dealloc_order(&mut self, ppn: usize, order: usize)
+    {
+        if !self.in_range(ppn)
         {
             panic!("try to return a page that no in scope");
         }
-        if self.frames.iter().map(|addr|{PhyPage::from(*addr)}).any(|page|{pg == page})
+        if self.meta[ppn - self.base] != META_USED | order as u8
+        {
+            panic!("try to dealloc {:?} which is free or not the start of an order {} block", PhyPage(ppn), order);
+        }
+        self.meta[ppn - self.base] = 0;
+        self.free += 1 << order;
+
+        let mut ppn = ppn;
+        let mut order = order;
+        while order + 1 < MAX_ORDER
         {
-            panic!("try to dealloc a page that is in frames");
+            let buddy = ppn ^ (1 << order);
+            if !self.in_range(buddy) || self.meta[buddy - self.base] != META_FREE | order as u8
+            {
+                break;
+            }
+            self.remove_free(buddy, order);
+            ppn = ppn.min(buddy);
+            order += 1;
         }
-        self.frames.push(usize::from(pg));
+        self.push_free(ppn, order);
+    }
+
+    fn set_slab(&mut self, ppn: usize, slab: bool)
+    {
+        let meta = &mut self.meta[ppn - self.base];
+        if slab
+        {
+            *meta |= META_SLAB;
+        }
+        else
+        {
+            *meta &= !META_SLAB;
+        }
+    }
+
+    fn is_slab(&self, ppn: usize) -> bool
+    {
+        self.in_range(ppn) && self.meta[ppn - self.base] & (META_USED | META_SLAB) == META_USED | META_SLAB
     }
 }
 
-type FrameAllocatorImpl = Allocator;
+impl FrameAllocator for BuddyAllocator
+{
+    fn new() -> Self
+    {
+        Self
+        {
+            base : 0,
+            end : 0,
+            meta : &mut [],
+            free_lists : [NIL; MAX_ORDER],
+            free : 0,
+            total : 0,
+        }
+    }
+
+    fn alloc(&mut self) -> Option<PhyPage>
+    {
+        self.alloc_order(0).map(PhyPage::from)
+    }
+
+    fn dealloc(&mut self, pg: PhyPage)
+    {
+        self.dealloc_order(usize::from(pg), 0);
+    }
+
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhyPage>
+    {
+        let order = Self::order_of(count);
+        if order >= MAX_ORDER
+        {
+            return None;
+        }
+        self.alloc_order(order).map(PhyPage::from)
+    }
+
+    fn dealloc_contiguous(&mut self, pg: PhyPage, count: usize)
+    {
+        self.dealloc_order(usize::from(pg), Self::order_of(count));
+    }
+
+    fn stats(&self) -> FrameStats
+    {
+        FrameStats
+        {
+            total: self.total,
+            free: self.free,
+        }
+    }
+}
+
+type FrameAllocatorImpl = BuddyAllocator;
 lazy_static!
 {
-    pub static ref FRAME_ALLOCATOR: up_safe_cell<FrameAllocatorImpl> = 
+    pub static ref FRAME_ALLOCATOR: up_safe_cell<FrameAllocatorImpl> =
     unsafe
     {
         up_safe_cell::new(FrameAllocatorImpl::new())
     };
 }
 
-pub fn init()
+/// why the frame allocator couldn't take the ram it was given
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameInitError
 {
-    let mut allocator = FRAME_ALLOCATOR.access();
-    let mut cur = allocator.begin;
-    let end = allocator.end;
-    while cur < end - PAGE_SIZE
-    {
-        allocator.frames.push(cur >> PAGE_BITS);
-        cur += PAGE_SIZE;
-    }
+    /// no free range at all
+    NoRam,
+    /// no free range can hold the state bytes for all of ram
+    NoRoomForState,
+}
+
+/// free holds the sorted, page aligned [start, end) ranges of ram nobody else uses
+pub fn init(free: &[(usize, usize)]) -> Result<(), FrameInitError>
+{
+    FRAME_ALLOCATOR.access().init_ranges(free)
 }
 
 pub fn alloc() -> Option<FrameTracker>
@@ -115,6 +364,39 @@ pub fn alloc() -> Option<FrameTracker>
     FRAME_ALLOCATOR.access().alloc().map(|ppn|{FrameTracker::new(ppn)})
 }
 
+/// count frames that are physically contiguous and aligned to count rounded up to a power of two
+pub fn alloc_contiguous(count: usize) -> Option<ContiguousFrames>
+{
+    let count = count.next_power_of_two();
+    FRAME_ALLOCATOR.access().alloc_contiguous(count).map(|ppn|{ContiguousFrames { ppn, count }})
+}
+
+/// a frame tagged as a slab, so a pointer can be told to be from a slab or not
+pub fn alloc_slab_frame() -> Option<PhyPage>
+{
+    let mut allocator = FRAME_ALLOCATOR.access();
+    let ppn = allocator.alloc()?;
+    allocator.set_slab(usize::from(ppn), true);
+    Some(ppn)
+}
+
+pub fn dealloc_slab_frame(ppn: PhyPage)
+{
+    let mut allocator = FRAME_ALLOCATOR.access();
+    allocator.set_slab(usize::from(ppn), false);
+    allocator.dealloc(ppn);
+}
+
+pub fn is_slab_frame(ppn: PhyPage) -> bool
+{
+    FRAME_ALLOCATOR.access().is_slab(usize::from(ppn))
+}
+
+pub fn frame_stats() -> FrameStats
+{
+    FRAME_ALLOCATOR.access().stats()
+}
+
 fn dealloc(ppn: PhyPage)
 {
     FRAME_ALLOCATOR.access().dealloc(ppn);
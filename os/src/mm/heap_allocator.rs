@@ -1,17 +1,111 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
-use buddy_system_allocator::LockedHeap;
-use crate::config::HEAP_SIZE;
+use buddy_system_allocator::{Heap, LockedHeap};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::config::{HEAP_SIZE, HEAP_GROW_PAGES, HEAP_GROW_MAX_PAGES, PAGE_SIZE};
+use super::addr::PhyAddr;
+use super::frame_allocator::alloc_contiguous;
+use super::slab;
+
+/// layouts with a slab cache go there, the rest and whatever a cache
+/// can't take for lack of frames to the heap
+struct KernelAllocator;
 
 #[global_allocator]
-static HEAP_ALLOCATOR : LockedHeap = LockedHeap::new();
+static KERNEL_ALLOCATOR : KernelAllocator = KernelAllocator;
+static HEAP_ALLOCATOR : LockedHeap = LockedHeap::empty();
 static mut HEAP_AREA : [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+/// frames the heap took from the frame allocator, they are never given back
+static HEAP_FRAMES : AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats
+{
+    /// bytes the heap manages, static area included
+    pub total: usize,
+    /// bytes handed out, rounded up to the buddy blocks
+    pub allocated: usize,
+    /// bytes asked for
+    pub requested: usize,
+    /// frames pulled from the frame allocator
+    pub frames: usize,
+}
+
+unsafe impl GlobalAlloc for KernelAllocator
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8
+    {
+        match slab::alloc(layout)
+        {
+            Some(ptr) => ptr,
+            None => heap_alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+    {
+        if !slab::dealloc(ptr, layout)
+        {
+            HEAP_ALLOCATOR.lock().dealloc(NonNull::new_unchecked(ptr), layout);
+        }
+    }
+}
 
 #[alloc_error_handler]
-pub fn handle_alloc_error(layout: core::alloc::Layout) -> !
+pub fn handle_alloc_error(layout: Layout) -> !
+{
+    panic!("Heap allocation error, layout = {:?}, {:?}", layout, heap_stats());
+}
+
+/// grow the heap whenever it has no block for layout, until it has or the frames run out
+fn heap_alloc(layout: Layout) -> *mut u8
 {
-    panic!("Heap allocation error, layout = {:?}", layout);
+    let mut heap = HEAP_ALLOCATOR.lock();
+    loop
+    {
+        if let Ok(ptr) = heap.alloc(layout)
+        {
+            return ptr.as_ptr();
+        }
+        if !grow_heap(&mut heap, layout)
+        {
+            return core::ptr::null_mut();
+        }
+    }
+}
+
+/// add frames to the heap, about doubling it but by no less than a block that fits layout,
+/// false if not even that many contiguous frames are left
+fn grow_heap(heap: &mut Heap, layout: Layout) -> bool
+{
+    let needed = match layout.size().max(layout.align()).checked_next_power_of_two()
+    {
+        Some(block) => ((block + PAGE_SIZE - 1) / PAGE_SIZE).max(1),
+        None => return false,
+    };
+    let mut pages = (heap.stats_total_bytes() / PAGE_SIZE).clamp(HEAP_GROW_PAGES, HEAP_GROW_MAX_PAGES).max(needed);
+    while pages >= needed
+    {
+        // contiguous frames are aligned to their count, so they hold a block of that size
+        if let Some(frames) = alloc_contiguous(pages)
+        {
+            let start = usize::from(PhyAddr::from(frames.ppn()));
+            let count = frames.count();
+            // the frames belong to the heap from now on
+            core::mem::forget(frames);
+            unsafe
+            {
+                heap.add_to_heap(start, start + count * PAGE_SIZE);
+            }
+            HEAP_FRAMES.fetch_add(count, Ordering::Relaxed);
+            return true;
+        }
+        pages /= 2;
+    }
+    false
 }
 
 pub fn init()
@@ -24,11 +118,21 @@ pub fn init()
     }
 }
 
+pub fn heap_stats() -> HeapStats
+{
+    let heap = HEAP_ALLOCATOR.lock();
+    HeapStats
+    {
+        total: heap.stats_total_bytes(),
+        allocated: heap.stats_alloc_actual(),
+        requested: heap.stats_alloc_user(),
+        frames: HEAP_FRAMES.load(Ordering::Relaxed),
+    }
+}
+
 
 pub fn heap_test()
 {
     let a = Box::new(5);
     println!("{}", a);
 }
-
-
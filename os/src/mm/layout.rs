@@ -0,0 +1,105 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::config::{MEM_START, MEM_END, PAGE_SIZE};
+use crate::fdt::Fdt;
+
+extern "C"
+{
+    fn ekernel();
+}
+
+/// where the ram past the kernel image is, as page aligned [start, end) ranges
+pub struct MemoryLayout
+{
+    /// identity mapped into the kernel space
+    pub ram: Vec<(usize, usize)>,
+    /// ram minus the reserved regions and the device tree blob, for the frame allocator
+    pub free: Vec<(usize, usize)>,
+}
+
+fn floor(v: usize) -> usize
+{
+    v & !(PAGE_SIZE - 1)
+}
+
+fn ceil(v: usize) -> usize
+{
+    (v + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// ranges with [start, end) cut out
+fn cut(ranges: Vec<(usize, usize)>, start: usize, end: usize) -> Vec<(usize, usize)>
+{
+    let mut res = Vec::new();
+    for (left, right) in ranges
+    {
+        if end <= left || start >= right
+        {
+            res.push((left, right));
+            continue;
+        }
+        if left < start
+        {
+            res.push((left, start));
+        }
+        if end < right
+        {
+            res.push((end, right));
+        }
+    }
+    res
+}
+
+impl MemoryLayout
+{
+    /// read the memory and reserved-memory nodes of the device tree at dtb,
+    /// falling back to [MEM_START, MEM_END) if there is no usable tree
+    pub fn discover(dtb: usize) -> Self
+    {
+        let fdt = Fdt::new(dtb);
+        let (mut ram, reserved) = match &fdt
+        {
+            Some(fdt) =>
+            {
+                let mut reserved = fdt.reserved_regions();
+                let (blob_start, blob_end) = fdt.range();
+                reserved.push((blob_start, blob_end - blob_start));
+                let ram: Vec<(usize, usize)> = fdt.memory_regions().into_iter()
+                    .map(|(start, size)| (start, start + size))
+                    .collect();
+                (ram, reserved)
+            },
+            None => (Vec::new(), Vec::new()),
+        };
+        if ram.is_empty()
+        {
+            println!("no memory found in the device tree at {:#x}, assuming [{:#x}, {:#x})", dtb, MEM_START, MEM_END);
+            ram = vec![(MEM_START, MEM_END)];
+        }
+
+        // the firmware and the kernel image sit at the start of ram
+        let kernel_end = ceil(ekernel as usize);
+        let mut ram: Vec<(usize, usize)> = ram.into_iter()
+            .map(|(start, end)| (ceil(start.max(kernel_end)), floor(end)))
+            .filter(|(start, end)| start < end)
+            .collect();
+        ram.sort();
+
+        let mut free = ram.clone();
+        for (start, size) in reserved.iter()
+        {
+            println!("reserved [{:#x}, {:#x})", start, start + size);
+            free = cut(free, floor(*start), ceil(start + size));
+        }
+        for (start, end) in ram.iter()
+        {
+            println!("ram [{:#x}, {:#x})", start, end);
+        }
+        MemoryLayout
+        {
+            ram,
+            free,
+        }
+    }
+}
@@ -5,12 +5,17 @@ extern crate xmas_elf;
 use bitflags::bitflags;
 use alloc::collections::BTreeMap;
 use alloc::vec::{self, Vec};
-use super::pagetable::PteFlags;
+use alloc::sync::Arc;
+use alloc::boxed::Box;
+use core::convert::TryInto;
+use super::pagetable::{PteFlags, PageTableEntry, PageWalk, LEAF_LEVEL, pages_at_level};
 use super::{VirPage, VirAddr, PageTable, PhyPage, alloc, StepOne, PhyAddr};
 use super::addr::VirtPageRange;
+use super::asid::KERNEL_ASID;
 use super::frame_allocator::FrameTracker;
-use crate::config::{PAGE_SIZE, TRAMPOLINE, MEM_END, TRAPFRAME, USERSTACK_TOP, USERSTACK_BOTTOM, PAGE_BITS};
-use crate::mm::output_virpage_entry;
+use super::swap::{SwapSlot, swap_out, swap_in, swap_available};
+use super::shm::ShmSegment;
+use crate::config::{PAGE_SIZE, TRAMPOLINE, TRAPFRAME, USERSTACK_TOP, USERSTACK_BOTTOM, USERSTACK_GUARD, USER_STACK_SIZE, PAGE_BITS, MMAP_BASE, MMAP_TOP, MMIO};
 use riscv::register::satp;
 use crate::sync::up_safe_cell;
 use lazy_static::lazy_static;
@@ -22,74 +27,773 @@ lazy_static!
     {
         up_safe_cell::new(MemorySet::new())
     };
+    /// untouched anonymous pages map this frame read-only until their first store
+    static ref ZERO_FRAME : Arc<FrameTracker> =
+    {
+        let frame = alloc().expect("no frame for the zero page");
+        frame.ppn.as_bytes().fill(0);
+        Arc::new(frame)
+    };
+}
+
+fn is_zero_frame(frame: &Arc<FrameTracker>) -> bool
+{
+    Arc::ptr_eq(frame, &ZERO_FRAME)
+}
+
+/// fnv-1a over the words of a page
+fn page_hash(bytes: &[u8]) -> u64
+{
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for word in bytes.chunks_exact(8)
+    {
+        hash ^= u64::from_le_bytes(word.try_into().unwrap());
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// frames met by a dedup pass so far, by the hash of their content,
+/// it holds the frames only for the length of the pass
+pub struct DedupTable
+{
+    frames: BTreeMap<u64, Arc<FrameTracker>>,
+}
+
+impl DedupTable
+{
+    /// read-only pages that are all zero go to the zero frame
+    pub fn new() -> Self
+    {
+        let mut frames = BTreeMap::new();
+        frames.insert(page_hash(ZERO_FRAME.ppn().as_bytes()), Arc::clone(&ZERO_FRAME));
+        DedupTable
+        {
+            frames,
+        }
+    }
+}
+
+/// why a page of a user address space couldn't be brought in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemError
+{
+    /// no area allows the access
+    BadAccess,
+    /// the frame allocator ran dry
+    OutOfMemory,
+    /// the address space already holds frame_limit frames
+    OverLimit,
 }
 
 pub struct MemorySet
 {
     pub pgt: PageTable,
-    areas: Vec<MemArea>,
+    /// boxed so each area comes from the mem area slab cache
+    areas: Vec<Box<MemArea>>,
+    /// the program break, heap is the lazy area [brk_start, brk)
+    pub brk_start: usize,
+    pub brk: usize,
+    /// most frames resident_frames may reach, None for no limit
+    pub frame_limit: Option<usize>,
+    /// where the clock of swap_out_one picks up
+    clock_hand: VirPage,
 }
 
 impl MemorySet
 {
     pub fn new() -> Self
+    {
+        Self::with_pgt(PageTable::new())
+    }
+
+    /// None if there is no frame for the root page table
+    pub fn try_new() -> Option<Self>
+    {
+        PageTable::try_new().map(Self::with_pgt)
+    }
+
+    fn with_pgt(pgt: PageTable) -> Self
     {
         MemorySet
         { 
-            pgt: PageTable::new(), 
+            pgt, 
             areas: Vec::new(),
+            brk_start: 0,
+            brk: 0,
+            frame_limit: None,
+            clock_hand: VirPage::from(0),
+        }
+    }
+
+    /// frames held by the areas and the page table, a frame shared
+    /// copy-on-write counts in every address space that maps it, the zero frame in none
+    pub fn resident_frames(&self) -> usize
+    {
+        self.areas.iter()
+            .map(|area| area.map.values().filter(|frame| !is_zero_frame(frame)).count())
+            .sum::<usize>() + self.pgt.frames.len()
+    }
+
+    fn check_limit(&self) -> Result<(), MemError>
+    {
+        match self.frame_limit
+        {
+            Some(limit) if self.resident_frames() >= limit => Err(MemError::OverLimit),
+            _ => Ok(()),
         }
     }
     
-    pub fn fork_one(&self) -> Self
+    /// child shares every framed page with self, writable pages become
+    /// read-only with RSW1 set in both page tables until the first store,
+    /// the trampoline is mapped in the child as well
+    pub fn fork_one(&mut self) -> Result<Self, MemError>
     {
-        let mut pgt = PageTable::new();
+        let mut pgt = PageTable::try_new().ok_or(MemError::OutOfMemory)?;
         let mut areas = Vec::new();
         
         for i in 0 .. self.areas.len()
         {
             let mut area = MemArea::from_exist(&self.areas[i]);
-            area.init_pages(&mut pgt);
-            area.copy_from_area(&self.areas[i]);
+            match area.map_type
+            {
+                MapType::Framed | MapType::Lazy =>
+                {
+                    area.share_from(&self.areas[i], &mut self.pgt, &mut pgt)?;
+                },
+                MapType::Indentical =>
+                {
+                    area.init_pages(&mut pgt)?;
+                },
+                MapType::Shared =>
+                {
+                    // both sides keep writing to the same frames
+                    area.map = self.areas[i].map.clone();
+                    for vpn in area.range
+                    {
+                        area.map_page_for(&mut pgt, vpn)?;
+                    }
+                },
+            }
+            areas.push(Box::new(area));
+        }
+
+        let mut res = MemorySet { pgt, areas, brk_start: self.brk_start, brk: self.brk, frame_limit: self.frame_limit, clock_hand: VirPage::from(0) };
+        res.map_trampoline()?;
+        Ok(res)
+    }
+
+    /// bring in a lazy page or break copy-on-write sharing for a store
+    pub fn handle_page_fault(&mut self, va: VirAddr, write: bool) -> Result<(), MemError>
+    {
+        let vpn = VirPage::from(va.floor());
+        if !self.areas.iter().any(|area| area.range.contains(vpn))
+        {
+            self.grow_userstack(vpn);
+        }
+        let index = match self.areas.iter().position(|area| area.range.contains(vpn))
+        {
+            Some(index) => index,
+            None => return Err(MemError::BadAccess),
+        };
+        let permit = self.areas[index].permit;
+        if !permit.intersects(MemPermit::R | MemPermit::W | MemPermit::X)
+        {
+            return Err(MemError::BadAccess);
+        }
+
+        let area = &self.areas[index];
+        let swapped = area.swapped.contains_key(&vpn);
+        if swapped || (area.map_type == MapType::Lazy && !area.map.contains_key(&vpn))
+        {
+            if !swapped && !write && area.starts_zero(vpn)
+            {
+                return self.areas[index].map_zero_for(&mut self.pgt, vpn);
+            }
+            self.check_limit()?;
+            if swapped
+            {
+                self.areas[index].swap_in_page(&mut self.pgt, vpn)?;
+            }
+            else
+            {
+                self.areas[index].map_page_for(&mut self.pgt, vpn)?;
+            }
+            if write && !permit.contains(MemPermit::W)
+            {
+                return Err(MemError::BadAccess);
+            }
+            return Ok(());
+        }
+
+        // harts without hardware A/D updates fault on pages the clock has aged
+        if let Some(pte) = self.pgt.find(vpn)
+        {
+            let flags = pte.flags();
+            let allowed = if write { flags.contains(PteFlags::W) } else { flags.intersects(PteFlags::R | PteFlags::X) };
+            if pte.valid() && allowed
+            {
+                let mut accessed = PteFlags::A;
+                if write
+                {
+                    accessed |= PteFlags::D;
+                }
+                if !flags.contains(accessed)
+                {
+                    *pte = PageTableEntry::new(pte.ppn(), flags | accessed);
+                    self.pgt.flush_page(vpn);
+                }
+                return Ok(());
+            }
+        }
+        if write
+        {
+            // the copy of the zero frame is the first frame of its own for the page
+            if self.areas[index].map.get(&vpn).map_or(false, is_zero_frame)
+            {
+                self.check_limit()?;
+            }
+            return self.areas[index].cow_page(&mut self.pgt, vpn);
+        }
+        Err(MemError::BadAccess)
+    }
+
+    /// point present pages of read-only areas at an identical frame the pass already
+    /// met, the frames not met yet go into table, the number of pages merged
+    pub fn merge_identical(&mut self, table: &mut DedupTable) -> usize
+    {
+        let mut merged = 0;
+        for area in self.areas.iter_mut()
+        {
+            if (area.map_type != MapType::Framed && area.map_type != MapType::Lazy) || area.permit.contains(MemPermit::W)
+            {
+                continue;
+            }
+            for (vpn, frame) in area.map.iter_mut()
+            {
+                let pte = match self.pgt.find(*vpn)
+                {
+                    Some(pte) if pte.valid() => pte,
+                    _ => continue,
+                };
+                let bytes = frame.ppn().as_bytes();
+                let hash = page_hash(bytes);
+                match table.frames.get(&hash)
+                {
+                    Some(other) if !Arc::ptr_eq(other, frame) && other.ppn().as_bytes() == bytes =>
+                    {
+                        *pte = PageTableEntry::new(other.ppn(), pte.flags());
+                        self.pgt.flush_page(*vpn);
+                        *frame = Arc::clone(other);
+                        merged += 1;
+                    },
+                    // the same frame again, or a hash collision
+                    Some(_) => {},
+                    None =>
+                    {
+                        table.frames.insert(hash, Arc::clone(frame));
+                    },
+                }
+            }
+        }
+        merged
+    }
+
+    /// clock over the private resident pages: pages with the accessed bit set get it
+    /// cleared and a second chance, the first page without it is swapped out,
+    /// false if there was nothing to swap out or no swap space for it
+    pub fn swap_out_one(&mut self) -> bool
+    {
+        if !swap_available()
+        {
+            return false;
+        }
+        let mut pages: Vec<(VirPage, usize)> = Vec::new();
+        for (i, area) in self.areas.iter().enumerate()
+        {
+            if area.map_type == MapType::Indentical || area.map_type == MapType::Shared
+            {
+                continue;
+            }
+            // a frame shared copy-on-write stays until it is private again
+            pages.extend(area.map.iter()
+                .filter(|(_, frame)| Arc::strong_count(frame) == 1)
+                .map(|(vpn, _)| (*vpn, i)));
+        }
+        if pages.is_empty()
+        {
+            return false;
+        }
+        pages.sort_by_key(|(vpn, _)| *vpn);
+        let start = pages.iter().position(|(vpn, _)| *vpn >= self.clock_hand).unwrap_or(0);
+        pages.rotate_left(start);
+
+        // the second lap finds the pages whose bit the first one cleared
+        for (vpn, i) in pages.iter().chain(pages.iter())
+        {
+            let pte = self.pgt.find(*vpn).unwrap();
+            if pte.flags().contains(PteFlags::A)
+            {
+                *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PteFlags::A);
+                self.pgt.flush_page(*vpn);
+                continue;
+            }
+            if !self.areas[*i].swap_out_page(&mut self.pgt, *vpn)
+            {
+                return false;
+            }
+            self.clock_hand = vpn.add(1);
+            return true;
         }
+        false
+    }
+
+    /// map pages anonymous pages at hint (or anywhere free if hint is taken),
+    /// fixed replaces whatever is mapped at hint instead, except the heap
+    pub fn mmap(&mut self, hint: VirPage, pages: usize, permit: MemPermit, fixed: bool) -> Option<VirPage>
+    {
+        let start = if fixed
+        {
+            let end = hint.add(pages);
+            if self.areas.iter().any(|area| area.heap && area.range.current_left() < end && hint < area.range.right())
+            {
+                return None;
+            }
+            self.munmap(hint, end);
+            hint
+        }
+        else if usize::from(hint) != 0 && self.is_free(hint, pages)
+        {
+            hint
+        }
+        else
+        {
+            self.find_free(pages)?
+        };
+
+        let area = MemArea::new(VirAddr::from(start), VirAddr::from(start.add(pages)), permit | MemPermit::U, MapType::Lazy);
+        self.push_area(area, None);
+        Some(start)
+    }
+
+    /// give [start, end) the permit, splitting the areas that straddle its edges
+    /// and merging what ends up alike, false if part of the range isn't mapped,
+    /// covers part of a shared attachment or would make a read-only one writable
+    pub fn mprotect(&mut self, start: VirPage, end: VirPage, permit: MemPermit) -> bool
+    {
+        let mut ranges: Vec<VirtPageRange> = self.areas.iter()
+            .map(|area| area.range)
+            .filter(|range| range.current_left() < end && start < range.right())
+            .collect();
+        ranges.sort_by_key(|range| range.current_left());
+        let mut covered = start;
+        for range in ranges
+        {
+            if range.current_left() > covered
+            {
+                return false;
+            }
+            covered = covered.max(range.right());
+        }
+        if covered < end || self.areas.iter().any(|area| area.map_type == MapType::Indentical && area.range.current_left() < end && start < area.range.right())
+        {
+            return false;
+        }
+        // a shared attachment changes as a whole, so shmdt still finds all of it
+        let shared_refused = self.areas.iter()
+            .filter(|area| area.map_type == MapType::Shared && area.range.current_left() < end && start < area.range.right())
+            .any(|area| area.range.current_left() < start || end < area.range.right() || (permit.contains(MemPermit::W) && !area.may_write));
+        if shared_refused
+        {
+            return false;
+        }
+
+        let mut i = 0;
+        while i < self.areas.len()
+        {
+            let left = self.areas[i].range.current_left();
+            let right = self.areas[i].range.right();
+            if right <= start || left >= end
+            {
+                i += 1;
+                continue;
+            }
+            if left < start
+            {
+                let rest = self.areas[i].split_off(start);
+                self.areas.insert(i + 1, Box::new(rest));
+                i += 1;
+                continue;
+            }
+            if right > end
+            {
+                let rest = self.areas[i].split_off(end);
+                self.areas.insert(i + 1, Box::new(rest));
+            }
+            self.areas[i].set_permit(&mut self.pgt, permit);
+            i += 1;
+        }
+        self.merge_areas();
+        true
+    }
+
+    /// join neighbouring areas that ended up with the same permit, heap pieces
+    /// only join each other so set_brk resizes nothing else
+    fn merge_areas(&mut self)
+    {
+        let mut i = 0;
+        while i < self.areas.len()
+        {
+            let next = (0..self.areas.len()).find(|j| {
+                *j != i && self.areas[i].can_merge(&self.areas[*j])
+            });
+            match next
+            {
+                Some(j) =>
+                {
+                    let next = *self.areas.remove(j);
+                    let target = if j < i { i - 1 } else { i };
+                    self.areas[target].merge(next);
+                    // the merged area may now reach another neighbour
+                    i = 0;
+                },
+                None => i += 1,
+            }
+        }
+    }
+
+    /// map the frames of seg at hint, or anywhere free if hint is 0
+    pub fn attach_shm(&mut self, seg: &ShmSegment, hint: VirPage, permit: MemPermit) -> Result<VirPage, MemError>
+    {
+        let pages = seg.frames.len();
+        let start = if usize::from(hint) != 0
+        {
+            if !self.is_free(hint, pages)
+            {
+                return Err(MemError::BadAccess);
+            }
+            hint
+        }
+        else
+        {
+            self.find_free(pages).ok_or(MemError::BadAccess)?
+        };
+        self.map_frames(start, &seg.frames, permit)?;
+        Ok(start)
+    }
+
+    /// a shared area at start over frames, which other address spaces map as well,
+    /// it never becomes writable unless permit lets it be now
+    pub fn map_frames(&mut self, start: VirPage, frames: &[Arc<FrameTracker>], permit: MemPermit) -> Result<(), MemError>
+    {
+        let mut area = MemArea::new(VirAddr::from(start), VirAddr::from(start.add(frames.len())), permit | MemPermit::U, MapType::Shared);
+        area.may_write = permit.contains(MemPermit::W);
+        for (i, frame) in frames.iter().enumerate()
+        {
+            area.map.insert(start.add(i), Arc::clone(frame));
+        }
+        for vpn in area.range
+        {
+            if let Err(err) = area.map_page_for(&mut self.pgt, vpn)
+            {
+                // only the pages before vpn made it into the page table
+                drop(area.split_off(vpn));
+                area.clear(&mut self.pgt);
+                return Err(err);
+            }
+        }
+        self.areas.push(Box::new(area));
+        Ok(())
+    }
+
+    /// unmap the shared area that starts at start
+    pub fn detach_shm(&mut self, start: VirPage) -> bool
+    {
+        match self.areas.iter().find(|area| area.map_type == MapType::Shared && area.range.current_left() == start)
+        {
+            Some(area) =>
+            {
+                let end = area.range.right();
+                self.munmap(start, end);
+                true
+            },
+            None => false,
+        }
+    }
 
-        MemorySet { pgt, areas }
+    /// unmap [start, end), splitting the areas that straddle its edges
+    pub fn munmap(&mut self, start: VirPage, end: VirPage)
+    {
+        let mut i = 0;
+        while i < self.areas.len()
+        {
+            let left = self.areas[i].range.current_left();
+            let right = self.areas[i].range.right();
+            if right <= start || left >= end
+            {
+                i += 1;
+                continue;
+            }
+            if left < start
+            {
+                let rest = self.areas[i].split_off(start);
+                self.areas.insert(i + 1, Box::new(rest));
+                i += 1;
+                continue;
+            }
+            if right > end
+            {
+                let rest = self.areas[i].split_off(end);
+                self.areas.insert(i + 1, Box::new(rest));
+            }
+            let mut area = self.areas.remove(i);
+            area.clear(&mut self.pgt);
+        }
+    }
+
+    /// move the program break to new_brk and return where it ends up,
+    /// the break stays put if new_brk is out of range or runs into another area
+    pub fn set_brk(&mut self, new_brk: usize) -> usize
+    {
+        if new_brk < self.brk_start || new_brk > MMAP_BASE
+        {
+            return self.brk;
+        }
+        let start = VirPage::from(VirAddr::from(self.brk_start));
+        let new_end = VirPage::from(VirAddr::from(new_brk).ceil());
+
+        if !self.areas.iter().any(|area| area.heap)
+        {
+            let mut heap = MemArea::new(VirAddr::from(start), VirAddr::from(start), MemPermit::R | MemPermit::W | MemPermit::U, MapType::Lazy);
+            heap.heap = true;
+            self.push_area(heap, None);
+        }
+        // mprotect may have split the heap, its last piece is the one that grows
+        let old_end = self.areas.iter().filter(|area| area.heap).map(|area| area.range.right()).max().unwrap();
+        if new_end > old_end
+        {
+            if self.areas.iter().any(|area| !area.heap && area.range.current_left() < new_end && old_end < area.range.right())
+            {
+                return self.brk;
+            }
+            let last = self.areas.iter().position(|area| area.heap && area.range.right() == old_end).unwrap();
+            self.areas[last].resize(&mut self.pgt, new_end);
+        }
+        else
+        {
+            // the pieces past new_end go, the one it falls in is cut short
+            let mut i = 0;
+            while i < self.areas.len()
+            {
+                let area = &self.areas[i];
+                if area.heap && area.range.current_left() >= new_end && area.range.current_left() > start
+                {
+                    let mut area = self.areas.remove(i);
+                    area.clear(&mut self.pgt);
+                    continue;
+                }
+                if area.heap && area.range.right() > new_end
+                {
+                    self.areas[i].resize(&mut self.pgt, new_end);
+                }
+                i += 1;
+            }
+        }
+        self.brk = new_brk;
+        self.brk
+    }
+
+    fn is_free(&self, start: VirPage, pages: usize) -> bool
+    {
+        let end = start.add(pages);
+        usize::from(VirAddr::from(end)) <= MMAP_TOP
+            && start < end
+            && !self.areas.iter().any(|area| area.range.current_left() < end && start < area.range.right())
+    }
+
+    fn find_free(&self, pages: usize) -> Option<VirPage>
+    {
+        let mut ranges: Vec<VirtPageRange> = self.areas.iter().map(|area| area.range).collect();
+        ranges.sort_by_key(|range| range.current_left());
+
+        let mut cur = VirPage::from(VirAddr::from(MMAP_BASE));
+        for range in ranges
+        {
+            if range.right() <= cur
+            {
+                continue;
+            }
+            if range.current_left() >= cur.add(pages)
+            {
+                break;
+            }
+            cur = range.right();
+        }
+        if self.is_free(cur, pages)
+        {
+            Some(cur)
+        }
+        else
+        {
+            None
+        }
     }
 
     pub fn root_satp(&self) -> usize
     {
-        1 << 63 | usize::from(self.pgt.root)
+        self.pgt.satp()
     }
 
-    pub fn push_area(&mut self, mut area: MemArea, data: Option<&[u8]>)
+    /// an area that needs frames right away panics without them, which only the
+    /// kernel's do, user images go through push_framed_area
+    pub fn push_area(&mut self, mut area: MemArea, data: Option<&'static [u8]>)
     {
-        area.init_pages(&mut self.pgt);
         if area.map_type ==  MapType::Indentical && data != None
         {
             panic!("try to put data in identical map");
         }
-        if let Some(bytes) = data 
+        if area.map_type == MapType::Lazy
+        {
+            area.data = data;
+        }
+        if let Err(err) = area.init_pages(&mut self.pgt)
+        {
+            panic!("push_area: can't map {:?}, {:?}", area.range.current_left(), err);
+        }
+        if let (MapType::Framed, Some(bytes)) = (area.map_type, data)
+        {
+            let offset = area.data_offset;
+            area.copy_from_slice(&mut self.pgt, offset, bytes);
+        }
+        self.areas.push(Box::new(area));
+    }
+
+    /// push a framed area whose pages start zeroed, with each (offset, bytes)
+    /// piece written offset bytes past its start
+    pub fn push_framed_area(&mut self, mut area: MemArea, pieces: &[(usize, &[u8])]) -> Result<(), MemError>
+    {
+        assert!(area.map_type == MapType::Framed, "push_framed_area: not a framed area");
+        // the frames mapped so far go with area, the page tables are freed with self
+        area.init_pages(&mut self.pgt)?;
+        for (offset, bytes) in pieces
         {
-            area.copy_from_slice(&mut self.pgt, bytes);
+            area.copy_from_slice(&mut self.pgt, *offset, bytes);
         }
-        self.areas.push(area);
+        self.areas.push(Box::new(area));
+        Ok(())
     }
 
-    pub fn map_userstack(&mut self, ppn: PhyPage) -> usize
+    /// write bytes at va in a space that isn't running, bringing its pages in and ignoring
+    /// their permissions, for the loader to relocate the image it just mapped
+    pub fn write_bytes(&mut self, va: usize, bytes: &[u8]) -> Result<(), MemError>
     {
-        self.pgt.map(VirPage::from(USERSTACK_BOTTOM >> PAGE_BITS), ppn, PteFlags::R | PteFlags::W | PteFlags::U);
+        let mut cur = 0;
+        while cur < bytes.len()
+        {
+            let at = va + cur;
+            let vpn = VirPage::from(VirAddr::from(at).floor());
+            let index = match self.areas.iter().position(|area| area.range.contains(vpn))
+            {
+                Some(index) if matches!(self.areas[index].map_type, MapType::Framed | MapType::Lazy) => index,
+                _ => return Err(MemError::BadAccess),
+            };
+            if self.areas[index].swapped.contains_key(&vpn)
+            {
+                self.check_limit()?;
+                self.areas[index].swap_in_page(&mut self.pgt, vpn)?;
+            }
+            else if !self.areas[index].map.contains_key(&vpn)
+            {
+                self.check_limit()?;
+                self.areas[index].map_page_for(&mut self.pgt, vpn)?;
+            }
+            // a frame someone else sees is not ours to write
+            let frame = self.areas[index].map.get(&vpn).unwrap();
+            if Arc::strong_count(frame) > 1
+            {
+                return Err(MemError::BadAccess);
+            }
+            let in_page = at % PAGE_SIZE;
+            let len = (PAGE_SIZE - in_page).min(bytes.len() - cur);
+            frame.ppn().as_bytes()[in_page..in_page + len].copy_from_slice(&bytes[cur..cur + len]);
+            cur += len;
+        }
+        Ok(())
+    }
+
+    /// push the lazy stack area, returns the initial sp
+    pub fn map_userstack(&mut self) -> usize
+    {
+        let stack = MemArea::new(VirAddr::from(USERSTACK_TOP - USER_STACK_SIZE),
+                                VirAddr::from(USERSTACK_TOP),
+                                MemPermit::R | MemPermit::W | MemPermit::U,
+                                MapType::Lazy);
+        self.push_area(stack, None);
         USERSTACK_TOP
     }
 
-    pub fn map_trampoline(&mut self)
+    /// extend the stack area down to vpn if vpn is inside the stack limit
+    fn grow_userstack(&mut self, vpn: VirPage)
     {
-        extern "C"
+        let bottom = VirPage::from(VirAddr::from(USERSTACK_BOTTOM));
+        let top = VirPage::from(VirAddr::from(USERSTACK_TOP));
+        if vpn < bottom || vpn >= top
         {
-            fn trampoline();
+            return;
         }
-        self.pgt.map((TRAMPOLINE>>PAGE_BITS).into(), (((trampoline as usize)>>PAGE_BITS)).into(), PteFlags::X | PteFlags::R);
+        // mprotect may have split the stack, only its lowest piece grows down
+        let lowest = self.areas.iter_mut()
+            .filter(|area| area.map_type == MapType::Lazy && bottom <= area.range.current_left() && area.range.right() <= top)
+            .min_by_key(|area| area.range.current_left());
+        if let Some(stack) = lowest
+        {
+            if vpn < stack.range.current_left()
+            {
+                stack.range = VirtPageRange::new(vpn, stack.range.right());
+            }
+        }
+    }
+
+    pub fn is_stack_guard(&self, va: VirAddr) -> bool
+    {
+        USERSTACK_GUARD <= usize::from(va) && usize::from(va) < USERSTACK_BOTTOM
+    }
+
+    /// the areas in address order
+    pub fn areas_info(&self) -> Vec<AreaInfo>
+    {
+        let mut infos: Vec<AreaInfo> = self.areas.iter().map(|area| area.info()).collect();
+        infos.sort_by_key(|info| usize::from(info.start));
+        infos
+    }
+
+    /// the page table walk for va as it stands, without faulting anything in
+    pub fn walk(&self, va: VirAddr) -> PageWalk
+    {
+        self.pgt.walk(va)
+    }
+
+    /// the area covering va, if any
+    pub fn area_at(&self, va: VirAddr) -> Option<&MemArea>
+    {
+        let vpn = VirPage::from(va);
+        self.areas.iter().map(|area| &**area).find(|area| area.range.contains(vpn))
     }
 
+    pub fn map_trampoline(&mut self) -> Result<(), MemError>
+    {
+        if self.pgt.try_map_at((TRAMPOLINE>>PAGE_BITS).into(), trampoline_page(), PteFlags::X | PteFlags::R, LEAF_LEVEL)
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(MemError::OutOfMemory)
+        }
+    }
+
+    /// the trapframe sits next to the trampoline and shares its tables,
+    /// so once the trampoline is mapped this never allocates
     pub fn map_trapframe(&mut self, ppn: PhyPage) -> usize
     {
         self.pgt.map(VirPage::from(TRAPFRAME >> PAGE_BITS), ppn, PteFlags::W | PteFlags::R);
@@ -98,8 +802,7 @@ impl MemorySet
 
     pub fn activate(&self)
     {
-        let root_ppn = self.pgt.root;
-        let token : usize = 1 << 63 | ((root_ppn.0));
+        let token = self.root_satp();
         unsafe
         {
             satp::write(token);
@@ -107,14 +810,15 @@ impl MemorySet
         }
     }
 
-    pub fn travel(&self)
+}
+
+fn trampoline_page() -> PhyPage
+{
+    extern "C"
     {
-        for area in self.areas.iter()
-        {
-            area.travel(&self.pgt);     
-        }
+        fn trampoline();
     }
-
+    PhyPage::from((trampoline as usize) >> PAGE_BITS)
 }
 
 bitflags! 
@@ -131,19 +835,48 @@ bitflags!
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+/// an area as the query api reports it
+#[derive(Clone, Copy, Debug)]
+pub struct AreaInfo
+{
+    pub start: VirAddr,
+    pub end: VirAddr,
+    pub permit: MemPermit,
+    pub map_type: MapType,
+    /// pages with a frame, shared, copy-on-write and zero page ones included
+    pub resident: usize,
+    /// pages out in swap
+    pub swapped: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MapType
 {
     Indentical,
     Framed,
+    /// framed, but each page gets its frame on the first fault
+    Lazy,
+    /// frames of a shared memory segment, present from the start and never copied
+    Shared,
 }
 
 pub struct MemArea
 {
     pub range: VirtPageRange,
     pub permit: MemPermit,
-    pub map: BTreeMap<VirPage, FrameTracker>,
+    pub map: BTreeMap<VirPage, Arc<FrameTracker>>,
     pub map_type: MapType,
+    /// initial content of a lazy area, starting data_offset bytes past range.current_left()
+    pub data: Option<&'static [u8]>,
+    pub data_offset: usize,
+    /// identical areas may use 2M/1G leaves where alignment allows
+    pub large_pages: bool,
+    /// pages whose pte holds a swap slot instead of a frame
+    pub swapped: BTreeMap<VirPage, Arc<SwapSlot>>,
+    /// false for read-only shared attachments, mprotect can't make them writable
+    pub may_write: bool,
+    /// the program heap, or a piece of it mprotect split off, only set_brk resizes it
+    pub heap: bool,
 }
 
 impl MemArea
@@ -156,25 +889,167 @@ impl MemArea
             permit:exist.permit,
             map:BTreeMap::new(),
             map_type:exist.map_type,
+            data:exist.data,
+            data_offset:exist.data_offset,
+            large_pages:exist.large_pages,
+            swapped:BTreeMap::new(),
+            may_write:exist.may_write,
+            heap:exist.heap,
        }
     }
 
-    pub fn copy_from_area(&mut self, other: &MemArea)
+    /// move the end of the area to right, unmapping whatever falls off
+    pub fn resize(&mut self, pgt: &mut PageTable, right: VirPage)
     {
-        for vpn in self.range
+        let left = self.range.current_left();
+        for vpn in VirtPageRange::new(right.max(left), self.range.right())
         {
-            let src_ppn = other.map.get(&vpn).unwrap().ppn();
-            let dst_ppn = self.map.get(&vpn).unwrap().ppn();
-            copy_page(dst_ppn, src_ppn);
+            self.unmap_page_for(pgt, vpn);
         }
+        self.range = VirtPageRange::new(left, right.max(left));
     }
 
-    pub fn travel(&self, pgt:&PageTable)
+    /// move the pages from at onwards into a new area
+    pub fn split_off(&mut self, at: VirPage) -> MemArea
     {
-        for vpn in self.range
+        let at_byte = self.byte_offset(at);
+        let (data, data_offset) = match self.data
         {
-            let entry = pgt.find(vpn).unwrap();
-            println!("{:?}", entry);
+            Some(data) if at_byte <= self.data_offset =>
+            {
+                self.data = None;
+                (Some(data), self.data_offset - at_byte)
+            },
+            Some(data) if at_byte < self.data_offset + data.len() =>
+            {
+                let (head, tail) = data.split_at(at_byte - self.data_offset);
+                self.data = Some(head);
+                (Some(tail), 0)
+            },
+            _ => (None, 0),
+        };
+        let rest = MemArea
+        {
+            range: VirtPageRange::new(at, self.range.right()),
+            permit: self.permit,
+            map: self.map.split_off(&at),
+            map_type: self.map_type,
+            data,
+            data_offset,
+            large_pages: self.large_pages,
+            swapped: self.swapped.split_off(&at),
+            may_write: self.may_write,
+            heap: self.heap,
+        };
+        self.range = VirtPageRange::new(self.range.current_left(), at);
+        rest
+    }
+
+    /// map the frames of other into pgt without copying them,
+    /// marking writable pages copy-on-write in both page tables
+    pub fn share_from(&mut self, other: &MemArea, other_pgt: &mut PageTable, pgt: &mut PageTable) -> Result<(), MemError>
+    {
+        let flags = self.present_flags(true);
+        for (vpn, frame) in other.map.iter()
+        {
+            other_pgt.set_flags(*vpn, flags);
+            match pgt.alloc_find(*vpn)
+            {
+                Some(pte) => *pte = PageTableEntry::new(frame.ppn(), flags),
+                None => return Err(MemError::OutOfMemory),
+            }
+            self.map.insert(*vpn, Arc::clone(frame));
+        }
+        // swapped pages share the slot, whoever faults first reads it into a private frame
+        for (vpn, slot) in other.swapped.iter()
+        {
+            match pgt.alloc_find(*vpn)
+            {
+                Some(pte) => *pte = PageTableEntry::swapped(slot.slot()),
+                None => return Err(MemError::OutOfMemory),
+            }
+            self.swapped.insert(*vpn, Arc::clone(slot));
+        }
+        Ok(())
+    }
+
+    /// write the private frame of vpn to swap and leave the slot in its pte
+    fn swap_out_page(&mut self, pgt: &mut PageTable, vpn: VirPage) -> bool
+    {
+        let slot = match swap_out(self.map.get(&vpn).unwrap().ppn())
+        {
+            Some(slot) => slot,
+            None => return false,
+        };
+        *pgt.find(vpn).unwrap() = PageTableEntry::swapped(slot.slot());
+        pgt.flush_page(vpn);
+        self.map.remove(&vpn);
+        self.swapped.insert(vpn, Arc::new(slot));
+        true
+    }
+
+    /// read vpn back from its swap slot into a private frame
+    pub fn swap_in_page(&mut self, pgt: &mut PageTable, vpn: VirPage) -> Result<(), MemError>
+    {
+        let frame = alloc().ok_or(MemError::OutOfMemory)?;
+        swap_in(self.swapped.get(&vpn).unwrap(), frame.ppn());
+        // the swapped pte keeps its tables around, so this can't run out of frames
+        pgt.map(vpn, frame.ppn(), PteFlags::from_bits(self.permit.bits()).unwrap());
+        self.swapped.remove(&vpn);
+        self.map.insert(vpn, Arc::new(frame));
+        Ok(())
+    }
+
+    /// give vpn a private writable frame, copying only if the frame is still shared
+    pub fn cow_page(&mut self, pgt: &mut PageTable, vpn: VirPage) -> Result<(), MemError>
+    {
+        let pte = match pgt.find(vpn)
+        {
+            Some(pte) if pte.valid() => pte,
+            _ => return Err(MemError::BadAccess),
+        };
+        if !pte.flags().contains(PteFlags::RSW1) || !self.permit.contains(MemPermit::W)
+        {
+            return Err(MemError::BadAccess);
+        }
+
+        let mut flags = pte.flags();
+        flags.remove(PteFlags::RSW1);
+        flags.insert(PteFlags::W);
+
+        let frame = self.map.get(&vpn).unwrap();
+        if Arc::strong_count(frame) == 1
+        {
+            *pte = PageTableEntry::new(frame.ppn(), flags);
+        }
+        else
+        {
+            let new_frame = alloc().ok_or(MemError::OutOfMemory)?;
+            copy_page(new_frame.ppn(), frame.ppn());
+            *pte = PageTableEntry::new(new_frame.ppn(), flags);
+            self.map.insert(vpn, Arc::new(new_frame));
+        }
+        pgt.flush_page(vpn);
+        Ok(())
+    }
+
+    pub fn info(&self) -> AreaInfo
+    {
+        let start = self.range.current_left();
+        let end = self.range.right();
+        let resident = match self.map_type
+        {
+            MapType::Indentical => usize::from(end) - usize::from(start),
+            _ => self.map.len(),
+        };
+        AreaInfo
+        {
+            start: start.into(),
+            end: end.into(),
+            permit: self.permit,
+            map_type: self.map_type,
+            resident,
+            swapped: self.swapped.len(),
         }
     }
 
@@ -188,10 +1063,102 @@ impl MemArea
             permit,
             map: BTreeMap::new(),
             map_type,
+            data: None,
+            data_offset: 0,
+            large_pages: false,
+            swapped: BTreeMap::new(),
+            may_write: true,
+            heap: false,
+        }
+    }
+
+    pub fn with_large_pages(mut self) -> Self
+    {
+        self.large_pages = true;
+        self
+    }
+
+    /// pte flags for a present page, a copy-on-write page has RSW1 in place of W,
+    /// and there is no V if the area allows no access, a V-only pte would be read
+    /// as a pointer to the next level
+    fn present_flags(&self, cow: bool) -> PteFlags
+    {
+        let mut flags = PteFlags::from_bits(self.permit.bits()).unwrap();
+        if cow && flags.contains(PteFlags::W)
+        {
+            flags.remove(PteFlags::W);
+            flags.insert(PteFlags::RSW1);
+        }
+        if flags.intersects(PteFlags::R | PteFlags::W | PteFlags::X)
+        {
+            flags.insert(PteFlags::V);
+        }
+        flags
+    }
+
+    /// change the permit and rewrite the ptes of the present pages,
+    /// pages that are still shared after a fork stay copy-on-write
+    pub fn set_permit(&mut self, pgt: &mut PageTable, permit: MemPermit)
+    {
+        self.permit = permit;
+        for (vpn, frame) in self.map.iter()
+        {
+            let cow = self.map_type != MapType::Shared && Arc::strong_count(frame) > 1;
+            let aged = pgt.find(*vpn).unwrap().flags() & (PteFlags::A | PteFlags::D);
+            pgt.set_flags(*vpn, self.present_flags(cow) | aged);
         }
     }
 
-    pub fn map_page_for(&mut self, pgt:&mut PageTable, vpn: VirPage)
+    /// whether next starts where self ends and the two can be one area
+    fn can_merge(&self, next: &MemArea) -> bool
+    {
+        let pages = usize::from(self.range.right()) - usize::from(self.range.current_left());
+        let data_fits = match (self.data, next.data)
+        {
+            (_, None) => true,
+            (Some(data), Some(next_data)) => self.data_offset + data.len() == pages << PAGE_BITS
+                && next.data_offset == 0
+                && data.as_ptr_range().end == next_data.as_ptr(),
+            (None, Some(_)) => false,
+        };
+        (self.map_type == MapType::Framed || self.map_type == MapType::Lazy)
+            && self.map_type == next.map_type
+            && self.permit == next.permit
+            && self.large_pages == next.large_pages
+            && self.heap == next.heap
+            && pages != 0
+            && self.range.right() == next.range.current_left()
+            && next.range.current_left() < next.range.right()
+            && data_fits
+    }
+
+    /// take over the pages of next, which must pass can_merge
+    fn merge(&mut self, mut next: MemArea)
+    {
+        self.range = VirtPageRange::new(self.range.current_left(), next.range.right());
+        self.map.append(&mut next.map);
+        self.swapped.append(&mut next.swapped);
+        if let (Some(data), Some(next_data)) = (self.data, next.data)
+        {
+            // can_merge checked that next_data follows data in memory
+            self.data = Some(unsafe { core::slice::from_raw_parts(data.as_ptr(), data.len() + next_data.len()) });
+        }
+    }
+
+    /// the level of the biggest leaf that can map vpn without leaving the area
+    fn leaf_level_for(&self, vpn: VirPage) -> usize
+    {
+        if !self.large_pages || self.map_type != MapType::Indentical
+        {
+            return LEAF_LEVEL;
+        }
+        (0..LEAF_LEVEL).find(|level| {
+            let pages = pages_at_level(*level);
+            usize::from(vpn) % pages == 0 && vpn.add(pages) <= self.range.right()
+        }).unwrap_or(LEAF_LEVEL)
+    }
+
+    pub fn map_page_for(&mut self, pgt:&mut PageTable, vpn: VirPage) -> Result<(), MemError>
     {
         let ppn:PhyPage;
         if let Some(pte) = pgt.find(vpn)
@@ -206,16 +1173,91 @@ impl MemArea
         {
             MapType::Framed =>
             {
-                let frame_tracker = alloc().unwrap();
+                let frame_tracker = alloc().ok_or(MemError::OutOfMemory)?;
                 ppn = frame_tracker.ppn;
-                self.map.insert(vpn, frame_tracker);
+                ppn.as_bytes().fill(0);
+                self.map.insert(vpn, Arc::new(frame_tracker));
             },
             MapType::Indentical =>
             {
                 ppn = PhyPage::from(usize::from(vpn));
             },
+            MapType::Shared =>
+            {
+                ppn = self.map.get(&vpn).unwrap().ppn();
+            },
+            MapType::Lazy =>
+            {
+                let frame_tracker = alloc().ok_or(MemError::OutOfMemory)?;
+                ppn = frame_tracker.ppn;
+                self.fill_page(ppn, vpn);
+                self.map.insert(vpn, Arc::new(frame_tracker));
+            },
+        }
+        let flags = self.present_flags(false);
+        match pgt.alloc_find(vpn)
+        {
+            Some(pte) => *pte = PageTableEntry::new(ppn, flags),
+            None =>
+            {
+                // the frame goes back with the map entry
+                self.map.remove(&vpn);
+                return Err(MemError::OutOfMemory);
+            },
+        }
+        pgt.flush_page(vpn);
+        Ok(())
+    }
+
+    /// bytes from the start of the area to vpn
+    fn byte_offset(&self, vpn: VirPage) -> usize
+    {
+        (usize::from(vpn) - usize::from(self.range.current_left())) << PAGE_BITS
+    }
+
+    /// the part of data that falls in the page vpn, with where it starts in the page
+    fn data_in_page(&self, vpn: VirPage) -> Option<(usize, &'static [u8])>
+    {
+        let data = self.data?;
+        let page = self.byte_offset(vpn);
+        let start = page.max(self.data_offset);
+        let end = (page + PAGE_SIZE).min(self.data_offset + data.len());
+        if start >= end
+        {
+            return None;
+        }
+        Some((start - page, &data[start - self.data_offset..end - self.data_offset]))
+    }
+
+    /// whether vpn has none of data and starts out all zero
+    fn starts_zero(&self, vpn: VirPage) -> bool
+    {
+        self.data_in_page(vpn).is_none()
+    }
+
+    /// map the zero frame at vpn like a copy-on-write page, the first store copies it
+    pub fn map_zero_for(&mut self, pgt: &mut PageTable, vpn: VirPage) -> Result<(), MemError>
+    {
+        let flags = self.present_flags(true);
+        match pgt.alloc_find(vpn)
+        {
+            Some(pte) => *pte = PageTableEntry::new(ZERO_FRAME.ppn(), flags),
+            None => return Err(MemError::OutOfMemory),
+        }
+        pgt.flush_page(vpn);
+        self.map.insert(vpn, Arc::clone(&ZERO_FRAME));
+        Ok(())
+    }
+
+    /// zero ppn and copy in the part of data that belongs to vpn
+    fn fill_page(&self, ppn: PhyPage, vpn: VirPage)
+    {
+        let bytes = ppn.as_bytes();
+        bytes.fill(0);
+        if let Some((start, src)) = self.data_in_page(vpn)
+        {
+            bytes[start..start + src.len()].copy_from_slice(src);
         }
-        pgt.map(vpn, ppn, PteFlags::from_bits(self.permit.bits()).unwrap());
     }
 
     pub fn unmap_page_for(&mut self, pgt:&mut PageTable, vpn: VirPage)
@@ -228,7 +1270,7 @@ impl MemArea
             }
             MapType::Framed =>
             {
-                if let None = self.map.remove(&vpn)
+                if self.map.remove(&vpn).is_none() && self.swapped.remove(&vpn).is_none()
                 {
                     panic!("in unmap_page_for, try to dealloc a page that not exist in pagetable");
                 }
@@ -237,49 +1279,85 @@ impl MemArea
                    pgt.unmap(vpn); 
                 }
             }
+            MapType::Lazy =>
+            {
+                if self.map.remove(&vpn).is_some() || self.swapped.remove(&vpn).is_some()
+                {
+                    pgt.unmap(vpn);
+                }
+            }
+            MapType::Shared =>
+            {
+                if self.map.remove(&vpn).is_some()
+                {
+                    pgt.unmap(vpn);
+                }
+            }
         }
     }
     
-    pub fn init_pages(&mut self, pgt:&mut PageTable)
+    pub fn init_pages(&mut self, pgt:&mut PageTable) -> Result<(), MemError>
     {
-        for vpn in self.range
+        if self.map_type == MapType::Lazy
         {
-            self.map_page_for(pgt, vpn);
+            return Ok(());
         }
+        let mut vpn = self.range.current_left();
+        while vpn < self.range.right()
+        {
+            let level = self.leaf_level_for(vpn);
+            if level == LEAF_LEVEL
+            {
+                self.map_page_for(pgt, vpn)?;
+            }
+            else
+            {
+                let flags = PteFlags::from_bits(self.permit.bits()).unwrap();
+                if !pgt.try_map_at(vpn, PhyPage::from(usize::from(vpn)), flags, level)
+                {
+                    return Err(MemError::OutOfMemory);
+                }
+            }
+            vpn = vpn.add(pages_at_level(level));
+        }
+        Ok(())
     }
 
     pub fn clear(&mut self, pgt: &mut PageTable)
     {
-        for vpn in self.range
+        let mut vpn = self.range.current_left();
+        while vpn < self.range.right()
         {
+            let pages = match pgt.find_with_level(vpn)
+            {
+                Some((pte, level)) if pte.valid() && self.map_type == MapType::Indentical => pages_at_level(level),
+                _ => 1,
+            };
             self.unmap_page_for(pgt, vpn);
+            vpn = vpn.add(pages);
         }
     }
 
-    pub fn copy_from_slice(&mut self, pgt: &mut PageTable, data: &[u8])
+    /// write data into the present pages of the area, offset bytes past its start
+    pub fn copy_from_slice(&mut self, pgt: &mut PageTable, offset: usize, data: &[u8])
     {
-        let mut vpn = self.range.current_left();
-        let end = self.range.right();
-        
-        if data.len() > (usize::from(end) - usize::from(vpn) << PAGE_BITS)
+        let left = self.range.current_left();
+        if offset + data.len() > (usize::from(self.range.right()) - usize::from(left) << PAGE_BITS)
         {
             panic!("in MemArea copy_from_slice, data too long");
         }
 
         let mut cur: usize = 0;
-        loop
+        while cur < data.len()
         {
-            let cur_end = (cur + PAGE_SIZE).min(data.len());
+            let at = offset + cur;
+            let in_page = at % PAGE_SIZE;
+            let cur_end = (cur + PAGE_SIZE - in_page).min(data.len());
             let src = &data[cur..cur_end];
-            let dst = &mut pgt.find(vpn).unwrap().ppn().as_bytes()[..src.len()];
+            let vpn = left.add(at / PAGE_SIZE);
+            let dst = &mut pgt.find(vpn).unwrap().ppn().as_bytes()[in_page..in_page + src.len()];
             dst.copy_from_slice(src);
-
             cur = cur_end;
-            if cur >= data.len()
-            {
-                break;
-            }
-            vpn.step_one();
         }
     }
 }
@@ -289,7 +1367,8 @@ fn output(area: &str, start:usize, end:usize)
     println!("{} begin at {:X} end at {:X}", area, start, end);
 }
 
-pub fn kernel_mem_init()
+/// ram holds the [start, end) ranges of ram past the kernel image
+pub fn kernel_mem_init(ram: &[(usize, usize)])
 {
    let mut kernel = KERNEL_SPACE.access();
    extern "C" 
@@ -304,7 +1383,8 @@ pub fn kernel_mem_init()
         fn ebss();
         fn ekernel();
    }
-   kernel.map_trampoline();
+   kernel.pgt.pin_asid(KERNEL_ASID);
+   kernel.map_trampoline().expect("no frame to map the trampoline");
     
    output("text", stext as usize, etext as usize);   
    let text_area = MemArea::new(usize::into(stext as usize),
@@ -330,12 +1410,6 @@ pub fn kernel_mem_init()
                                 (MemPermit::R | MemPermit::W), 
                                 MapType::Indentical);
 
-   output("mem", ekernel as usize, MEM_END);   
-   let mem_area = MemArea::new(VirAddr::from(ekernel as usize).ceil(),
-                                VirAddr::from(MEM_END).floor(),
-                                (MemPermit::R | MemPermit::W), 
-                                MapType::Indentical);
-
     println!("putting text");
     kernel.push_area(text_area, None);
     println!("putting rodata");
@@ -344,48 +1418,29 @@ pub fn kernel_mem_init()
     kernel.push_area(data_area, None);
     println!("putting bss");
     kernel.push_area(bss_area, None);
-    println!("putting mem");
-    kernel.push_area(mem_area, None);
-}
-
-///(entry_point, MemmorySet)
-pub fn to_prog(elf_data: &[u8]) -> (usize, MemorySet)
-{
-    let mut res = MemorySet::new();
-    res.map_trampoline();
+    for (start, end) in ram
+    {
+        output("mem", *start, *end);
+        let mem_area = MemArea::new(VirAddr::from(*start),
+                                    VirAddr::from(*end),
+                                    MemPermit::R | MemPermit::W,
+                                    MapType::Indentical).with_large_pages();
+        println!("putting mem");
+        kernel.push_area(mem_area, None);
+    }
 
-    let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
-    let elf_header = elf.header;
-    assert_eq!(elf_header.pt1.magic, [0x7f, 0x45, 0x4c, 0x46], "elf magic error");
-    let ph_count = elf_header.pt2.ph_count();
-    for i in 0..ph_count
+    for (start, len) in MMIO
     {
-        let ph = elf.program_header(i).unwrap();
-        if ph.get_type().unwrap() == xmas_elf::program::Type::Load
-        {        
-            let start = ph.virtual_addr() as usize;
-            let end = start + ph.mem_size() as usize;
-            let mut permit = MemPermit::U;
-            if ph.flags().is_read()
-            {
-                permit |= MemPermit::R;
-            }
-            if ph.flags().is_write()
-            {
-                permit |= MemPermit::W;
-            }
-            if ph.flags().is_execute()
-            {
-                permit |= MemPermit::X;
-            }
-            let mut area = MemArea::new(VirAddr::from(start), VirAddr::from(end), permit, MapType::Framed);
-            let progdata = &elf.input[(ph.offset() as usize)..((ph.offset() + ph.file_size()) as usize)];
-            res.push_area(area, Some(progdata));
-        }
+        output("mmio", *start, *start + *len);
+        let mmio_area = MemArea::new(VirAddr::from(*start),
+                                    VirAddr::from(*start + *len),
+                                    MemPermit::R | MemPermit::W,
+                                    MapType::Indentical);
+        kernel.push_area(mmio_area, None);
     }
-    (elf_header.pt2.entry_point() as usize, res)
 }
 
+///(entry_point, MemmorySet)
 pub fn copy_page(dst: PhyPage, src: PhyPage)
 {
     let dst_addr = usize::from(PhyAddr::from(dst)) as *mut u8;
@@ -398,11 +1453,26 @@ pub fn copy_page(dst: PhyPage, src: PhyPage)
 pub fn test()
 {
     super::heap_init();
-    super::frame_init();
-    kernel_mem_init();
+    let layout = super::layout::MemoryLayout::discover(0);
+    super::frame_init(&layout.free).expect("can't set up the frame allocator");
+    kernel_mem_init(&layout.ram);
     let ker = KERNEL_SPACE.access();
     println!("{:?}", ker.pgt.root);
-    
-    ker.travel();
+
+    for info in ker.areas_info()
+    {
+        println!("{:?}", info);
+        // the kernel is mapped one to one, at either end of every area
+        for va in [usize::from(info.start), usize::from(info.end) - 1]
+        {
+            if info.map_type == MapType::Indentical
+            {
+                let walk = ker.walk(va.into());
+                assert_eq!(walk.pa.map(usize::from), Some(va), "{:?}", walk);
+            }
+        }
+    }
+    let trampoline = ker.walk(TRAMPOLINE.into());
+    assert_eq!(trampoline.pa.map(usize::from), Some(usize::from(PhyAddr::from(trampoline_page()))));
     ker.activate();
 }
@@ -3,69 +3,60 @@ mod heap_allocator;
 mod frame_allocator;
 mod pagetable;
 mod memory_set;
+mod asid;
+mod swap;
+mod shm;
+mod uaccess;
+mod slab;
+mod layout;
+mod elf;
 
-pub use heap_allocator::{init as heap_init, heap_test};
-pub use frame_allocator::{init as frame_init, alloc, FrameTracker};
-pub use pagetable::{PageTableEntry, PageTable, get_arr_from_userspace, get_str_from_userspace};
+pub use heap_allocator::{init as heap_init, heap_test, heap_stats, HeapStats};
+pub use frame_allocator::{init as frame_init, alloc, alloc_contiguous, frame_stats, FrameTracker, ContiguousFrames, FrameStats};
+pub use pagetable::{PageTableEntry, PageTable, PageWalk};
 pub use addr::*;
-pub use memory_set::{to_prog, KERNEL_SPACE, MemorySet, kernel_mem_init, test, copy_page};
-use crate::config::TRAMPOLINE;
+pub use asid::flush_on_switch;
+pub use shm::SHM_MANAGER;
+pub use slab::{register as slab_register, register_arc, register_box, cache_alloc, cache_free, slab_stats, SlabStats};
+pub use uaccess::{copy_from_user, copy_to_user, read_user, write_user, read_user_cstr, read_user_strv, UserError, USER_CSTR_MAX};
+pub use elf::{to_prog, init_stack, ElfError};
+pub use memory_set::{KERNEL_SPACE, MemorySet, MemPermit, MemError, MapType, AreaInfo, DedupTable, kernel_mem_init, test, copy_page};
+use crate::config::{TRAMPOLINE, SATP_MODE, PAGE_LEVELS};
 pub use riscv::register::satp;
 pub use core::arch::asm;
 
 
-use self::pagetable::{PteFlags};
 
-pub fn outputentry(ppn: PhyPage, level: usize, cur: usize)
+
+/// dtb is the device tree the firmware passed, 0 if there is none
+pub fn init(dtb: usize)
 {
-    let ptes = ppn.as_ptes();
-    let mut i = 0;
-    for pte in ptes
+    heap_init();
+    let layout = layout::MemoryLayout::discover(dtb);
+    if let Err(err) = frame_init(&layout.free)
     {
-        if pte.valid()
-        {
-            if level == 2
-            {
-                println!("{:x} -> {:?}", cur<<9 | i, pte);
-                println!("{:x}", pte.entry);
-            }
-            else if level < 2
-            {
-                outputentry(pte.ppn(), level+1, (cur<<((2-level)*9)) | i);
-            }
-        }
-        i += 1;
+        panic!("can't set up the frame allocator: {:?}", err);
     }
-}
-
-pub fn output_virpage_entry(vpn: VirPage, pgt: PhyPage)
-{
-    let indexs = vpn.indexs();
-    let mut cur = pgt;
-    let mut pte = PageTableEntry::new(PhyPage::from(0), PteFlags::empty());
-    for index in indexs
+    // blocks of these layouts allocated so far stay in the heap
+    register_arc::<FrameTracker>("frame");
+    register_arc::<swap::SwapSlot>("swap slot");
+    register_box::<memory_set::MemArea>("mem area");
+    for cache in slab_stats()
     {
-        let ptes = cur.as_ptes();
-        pte = ptes[index];
-        if pte.valid()
-        {
-            cur = pte.ppn();
-        }
-        else
-        {
-            println!("{:?} has no valid pte", vpn);
-            return;
-        }
+        println!("slab {}: {} byte objects, {} per slab", cache.name, cache.size, cache.per_slab);
     }
-    println!("{:?} -> {:?}",vpn, pte);
-}
-
-
-pub fn init()
-{
-    heap_init();
-    frame_init();
-    kernel_mem_init();
+    let stats = frame_stats();
+    println!("frames: {} free of {}", stats.free, stats.total);
+    kernel_mem_init(&layout.ram);
+    let heap = heap_stats();
+    println!("heap: {} of {} bytes used", heap.allocated, heap.total);
     let m = KERNEL_SPACE.access();
     m.activate();
+    // satp is WARL, a hart without the mode keeps the old one
+    if satp::read().bits() >> 60 != SATP_MODE
+    {
+        panic!("the hart does not support paging with {} levels", PAGE_LEVELS);
+    }
+    asid::init();
+    swap::init();
 }
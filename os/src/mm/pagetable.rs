@@ -1,6 +1,5 @@
 extern crate bitflags;
 extern crate alloc;
-use alloc::string::String;
 use bitflags::bitflags;
 use super::{VirAddr, PhyAddr, VirPage, PhyPage, StepOne};
 use super::frame_allocator::{alloc, FrameTracker, FRAME_ALLOCATOR};
@@ -9,8 +8,9 @@ use alloc::vec::Vec;
 use core::fmt::{Formatter, Debug};
 use core::option::Option;
 use core::usize;
-use crate::config::{PAGE_SIZE, PAGE_BITS};
-use crate::task::get_current_task_satp;
+use core::cell::Cell;
+use super::asid::{self, ASID_ALLOCATOR, PINNED};
+use crate::config::{PAGE_SIZE, PAGE_BITS, PAGE_LEVELS};
 
 bitflags! 
 {
@@ -36,6 +36,17 @@ pub struct PageTableEntry
     pub entry: usize,
 }
 
+/// the entries a translation went through, see PageTable::walk
+#[derive(Clone, Copy, Debug)]
+pub struct PageWalk
+{
+    /// entries from the root down, the first levels of them are filled
+    pub ptes: [PageTableEntry; PAGE_LEVELS],
+    pub levels: usize,
+    /// where the address ends up, None if the walk met an invalid entry
+    pub pa: Option<PhyAddr>,
+}
+
 impl Debug for PageTableEntry
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
@@ -87,28 +98,77 @@ impl PageTableEntry
     {
         self.flags().contains(PteFlags::X)
     }
+
+    /// a valid entry with any of R W X maps memory instead of pointing to the next level
+    pub fn is_leaf(&self) -> bool
+    {
+        self.valid() && self.flags().intersects(PteFlags::R | PteFlags::W | PteFlags::X)
+    }
+
+    /// an invalid entry for a page that sits in swap slot, RSW2 tells it from an empty entry
+    pub fn swapped(slot: usize) -> Self
+    {
+        Self
+        {
+            entry: (slot << 10) | (PteFlags::RSW2.bits as usize),
+        }
+    }
+
+    pub fn swap_slot(&self) -> Option<usize>
+    {
+        if !self.valid() && self.flags().contains(PteFlags::RSW2)
+        {
+            Some(self.entry >> 10)
+        }
+        else
+        {
+            None
+        }
+    }
+}
+
+/// level of the walk that holds 4K leaves, a leaf one level up maps 2M,
+/// two up 1G and three up (only with Sv48) 512G
+pub const LEAF_LEVEL : usize = PAGE_LEVELS - 1;
+
+/// number of 4K pages mapped by a leaf at level
+pub fn pages_at_level(level: usize) -> usize
+{
+    1 << (9 * (LEAF_LEVEL - level))
 }
 
 pub struct PageTable
 {
     pub root : PhyPage,
     pub frames : Vec<FrameTracker>,
+    /// hardware asid, only meaningful while asid_generation is the allocator's
+    asid : Cell<usize>,
+    asid_generation : Cell<usize>,
 }
 
 impl PageTable
 {
     pub fn new() -> Self
     {        
-        let res = alloc();
-        if let Some(frame) = res
+        match Self::try_new()
         {
-            return Self
-            {
-                root : frame.ppn,
-                frames : vec![frame], 
-            };
+            Some(pgt) => pgt,
+            None => panic!("PageTable alloc fail, out of memory"),
         }
-        panic!("PageTable alloc fail, out of memory");
+    }
+
+    /// None if there is no frame left for the root table
+    pub fn try_new() -> Option<Self>
+    {
+        let frame = alloc()?;
+        frame.ppn.as_bytes().fill(0);
+        Some(Self
+        {
+            root : frame.ppn,
+            frames : vec![frame], 
+            asid : Cell::new(0),
+            asid_generation : Cell::new(0),
+        })
     }
 
     pub fn root_page(&self) -> PhyPage
@@ -122,20 +182,104 @@ impl PageTable
         {
             root: PhyPage::from(root),
             frames: Vec::new(),
+            asid : Cell::new(0),
+            asid_generation : Cell::new(0),
         }
     }
 
+    /// keep asid for good, it is never handed out by the allocator
+    pub fn pin_asid(&self, asid: usize)
+    {
+        self.asid.set(asid);
+        self.asid_generation.set(PINNED);
+    }
+
+    /// the asid of this address space, picking a new one if it has none in the current generation
+    pub fn asid(&self) -> usize
+    {
+        let generation = self.asid_generation.get();
+        if generation != PINNED && generation != ASID_ALLOCATOR.access().generation()
+        {
+            let (generation, asid) = ASID_ALLOCATOR.access().alloc();
+            self.asid.set(asid);
+            self.asid_generation.set(generation);
+        }
+        self.asid.get()
+    }
+
+    pub fn satp(&self) -> usize
+    {
+        asid::satp_token(usize::from(self.root), self.asid())
+    }
+
+    /// drop the tlb entry of vpn after its pte changed
+    pub fn flush_page(&self, vpn: VirPage)
+    {
+        let va = usize::from(VirAddr::from(vpn));
+        let generation = self.asid_generation.get();
+        let allocator = ASID_ALLOCATOR.access();
+        if generation == PINNED || !allocator.supported()
+        {
+            asid::flush_page_global(va);
+        }
+        else if generation == allocator.generation()
+        {
+            asid::flush_page(va, self.asid.get());
+        }
+        // an older generation was flushed as a whole when the asids rolled over
+    }
+
     pub fn translate_va(&self, vir: VirAddr) -> Option<PhyAddr>
     {
-        self.find(VirPage::from(vir))
-            .map(|pte| {
+        self.find_with_level(VirPage::from(vir))
+            .filter(|(pte, _)| pte.valid())
+            .map(|(pte, level)| {
+            let in_leaf = usize::from(vir) & ((pages_at_level(level) << PAGE_BITS) - 1);
             PhyAddr::from(
-                (usize::from(pte.ppn()) << PAGE_BITS)+ vir.offset()
+                (usize::from(pte.ppn()) << PAGE_BITS) + in_leaf
             )
             })
     }
 
+    /// the entries translating va goes through from the root down, stopping at
+    /// a leaf or an invalid entry, nothing is allocated or faulted in
+    pub fn walk(&self, va: VirAddr) -> PageWalk
+    {
+        let mut walk = PageWalk
+        {
+            ptes: [PageTableEntry { entry: 0 }; PAGE_LEVELS],
+            levels: 0,
+            pa: None,
+        };
+        let mut cur = self.root;
+        for (level, index) in VirPage::from(va).indexs().iter().enumerate()
+        {
+            let pte = cur.as_ptes()[*index];
+            walk.ptes[level] = pte;
+            walk.levels = level + 1;
+            if !pte.valid()
+            {
+                break;
+            }
+            if level == LEAF_LEVEL || pte.is_leaf()
+            {
+                let in_leaf = usize::from(va) & ((pages_at_level(level) << PAGE_BITS) - 1);
+                walk.pa = Some(PhyAddr::from((usize::from(pte.ppn()) << PAGE_BITS) + in_leaf));
+                break;
+            }
+            cur = pte.ppn();
+        }
+        walk
+    }
+
     pub fn find(&self, vpn: VirPage) -> Option<&mut PageTableEntry>
+    {
+        self.find_with_level(vpn).map(|(pte, _)| pte)
+    }
+
+    /// the leaf that maps vpn at whatever level it sits, or the empty
+    /// last-level slot for vpn, along with the level it was found at
+    pub fn find_with_level(&self, vpn: VirPage) -> Option<(&mut PageTableEntry, usize)>
     {
         let indexs = vpn.indexs();
         let mut cur = self.root;
@@ -143,9 +287,9 @@ impl PageTable
         {
             let ptes = cur.as_ptes();
             let pte = ptes.get_mut(*offset).unwrap();
-            if i == 2
+            if i == LEAF_LEVEL || pte.is_leaf()
             {
-                return Some(pte);
+                return Some((pte, i));
             }
             if pte.valid()
             {
@@ -159,7 +303,14 @@ impl PageTable
         None
     }
 
-    pub fn alloc_find(&mut self, vpn: VirPage) ->&mut PageTableEntry
+    pub fn alloc_find(&mut self, vpn: VirPage) -> Option<&mut PageTableEntry>
+    {
+        self.alloc_find_at(vpn, LEAF_LEVEL)
+    }
+
+    /// the slot for vpn at level, allocating the tables on the way,
+    /// None if a table can't be allocated
+    pub fn alloc_find_at(&mut self, vpn: VirPage, level: usize) -> Option<&mut PageTableEntry>
     {
         let indexs = vpn.indexs();
         let mut cur = self.root;
@@ -167,9 +318,13 @@ impl PageTable
         {
             let ptes = cur.as_ptes();
             let pte = ptes.get_mut(*offset).unwrap();
-            if i == 2
+            if i == level
+            {
+                return Some(pte);
+            }
+            if pte.is_leaf()
             {
-                return pte;
+                panic!("{:?} is already inside a large page", vpn);
             }
             if pte.valid()
             {
@@ -177,7 +332,8 @@ impl PageTable
             }
             else
             {
-                let new_frame = alloc().unwrap();
+                let new_frame = alloc()?;
+                new_frame.ppn.as_bytes().fill(0);
                 cur = new_frame.ppn;
                 *pte = PageTableEntry::new(new_frame.ppn, PteFlags::V);
                 self.frames.push(new_frame);
@@ -186,15 +342,41 @@ impl PageTable
         panic!("should not reach here in alloc_find");
     }
 
-    pub fn map(&mut self, vpn: VirPage, ppn: PhyPage, mut flags: PteFlags)
+    pub fn map(&mut self, vpn: VirPage, ppn: PhyPage, flags: PteFlags)
+    {
+        self.map_at(vpn, ppn, flags, LEAF_LEVEL);
+    }
+
+    /// map a leaf at level, both vpn and ppn must be aligned to its size
+    pub fn map_at(&mut self, vpn: VirPage, ppn: PhyPage, flags: PteFlags, level: usize)
     {
-        let entry = self.alloc_find(vpn);
+        if !self.try_map_at(vpn, ppn, flags, level)
+        {
+            panic!("no frame left for the page table mapping {:?}", vpn);
+        }
+    }
+
+    /// map_at that returns false instead of panicking when a table can't be allocated
+    pub fn try_map_at(&mut self, vpn: VirPage, ppn: PhyPage, mut flags: PteFlags, level: usize) -> bool
+    {
+        let pages = pages_at_level(level);
+        if usize::from(vpn) % pages != 0 || usize::from(ppn) % pages != 0
+        {
+            panic!("{:?} -> {:?} is not aligned to a level {} leaf", vpn, ppn, level);
+        }
+        let entry = match self.alloc_find_at(vpn, level)
+        {
+            Some(entry) => entry,
+            None => return false,
+        };
         if entry.valid()
         {
             panic!("remap error");
         }
         flags.set(PteFlags::V, true);
         *entry = PageTableEntry::new(ppn, flags);
+        self.flush_page(vpn);
+        true
     }
     
     pub fn unmap(&mut self, vpn: VirPage)
@@ -202,6 +384,7 @@ impl PageTable
         if let Some(entry) = self.find(vpn)
         {
             *entry = PageTableEntry::empty();
+            self.flush_page(vpn);
         }
         else
         {
@@ -209,61 +392,19 @@ impl PageTable
         }
     }
 
-    pub fn translate(&self, vpn: VirPage) -> Option<PageTableEntry>
-    {
-        self.find(vpn).map(|pte| {*pte})
-    }
-}
-
-pub fn get_arr_from_userspace(user_root: usize, user_src: usize, len: usize) -> Vec<&'static mut [u8]>
-{
-    let fake_pgt = PageTable::fake(user_root);
-    let mut cur = VirAddr(user_src);
-    let end = VirAddr(user_src + len);
-    let mut v : Vec<&'static mut [u8]> = Vec::new();
-    let end_vir_page = VirPage::from(end);
-
-    while cur < end
+    /// rewrite the flags of the leaf for vpn, keeping where it points
+    pub fn set_flags(&mut self, vpn: VirPage, flags: PteFlags)
     {
-        let low_vir_page = VirPage::from(cur.floor());
-        let pte = fake_pgt.find(low_vir_page).unwrap();
-        let bytes = pte.ppn().as_bytes();
-        let start_index = usize::from(cur) - usize::from(VirAddr::from(low_vir_page));
-        if low_vir_page == end_vir_page
+        match self.find(vpn)
         {
-            let finnal = usize::from(end) - usize::from(VirAddr::from(low_vir_page));
-            v.push(&mut bytes[start_index..finnal]);
-            cur = end;
-        }
-        else
-        {
-            v.push(&mut bytes[start_index..]);
-            cur = cur.ceil();
+            Some(pte) => *pte = PageTableEntry::new(pte.ppn(), flags),
+            None => panic!("set_flags: {:?} has no pte", vpn),
         }
+        self.flush_page(vpn);
     }
-    v
-}
 
-pub fn get_str_from_userspace(str_src: usize) -> String
-{
-    let user_root = get_current_task_satp();
-    let fake_pgt = PageTable::fake(user_root);
-    let mut cur = str_src;
-    let begin = str_src;
-    let mut res = String::new();
-    loop
+    pub fn translate(&self, vpn: VirPage) -> Option<PageTableEntry>
     {
-        let phyaddr = fake_pgt.translate_va(VirAddr::from(cur)).unwrap();
-        let ch :u8 = *(phyaddr.get_ref());
-        if ch == b'\0'
-        {
-            break;
-        }
-        else
-        {
-            res.push(ch.into());
-        }
-        cur = cur + 1;
+        self.find(vpn).map(|pte| {*pte})
     }
-    res
 }
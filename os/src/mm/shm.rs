@@ -0,0 +1,71 @@
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use crate::sync::up_safe_cell;
+use super::frame_allocator::{alloc, FrameTracker};
+
+/// a shared memory object, its frames stay alive as long as the segment
+/// is in the table or some address space still maps them
+pub struct ShmSegment
+{
+    pub key: usize,
+    pub size: usize,
+    pub frames: Vec<Arc<FrameTracker>>,
+}
+
+pub struct ShmManager
+{
+    segments: BTreeMap<usize, ShmSegment>,
+    next_id: usize,
+}
+
+lazy_static!
+{
+    pub static ref SHM_MANAGER: up_safe_cell<ShmManager> =
+    unsafe
+    {
+        up_safe_cell::new(ShmManager { segments: BTreeMap::new(), next_id: 0 })
+    };
+}
+
+impl ShmManager
+{
+    /// id of the segment created under key, private segments (key 0) are never found
+    pub fn find(&self, key: usize) -> Option<usize>
+    {
+        if key == 0
+        {
+            return None;
+        }
+        self.segments.iter().find(|(_, seg)| seg.key == key).map(|(id, _)| *id)
+    }
+
+    /// a zeroed segment of pages frames, None if memory runs out
+    pub fn create(&mut self, key: usize, size: usize, pages: usize) -> Option<usize>
+    {
+        let mut frames = Vec::new();
+        for _ in 0..pages
+        {
+            let frame = alloc()?;
+            frame.ppn().as_bytes().fill(0);
+            frames.push(Arc::new(frame));
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.segments.insert(id, ShmSegment { key, size, frames });
+        Some(id)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&ShmSegment>
+    {
+        self.segments.get(&id)
+    }
+
+    /// forget the segment, attached address spaces keep their frames until they detach
+    pub fn remove(&mut self, id: usize) -> bool
+    {
+        self.segments.remove(&id).is_some()
+    }
+}
@@ -0,0 +1,338 @@
+extern crate alloc;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use lazy_static::lazy_static;
+use crate::config::PAGE_SIZE;
+use crate::sync::up_safe_cell;
+use super::addr::{PhyAddr, PhyPage};
+use super::frame_allocator::{alloc_slab_frame, dealloc_slab_frame, is_slab_frame};
+
+const MAX_CACHES : usize = 16;
+/// a slab is one frame, objects too big to fit this many in one are left to the heap
+const MIN_OBJECTS : usize = 4;
+const NIL : usize = usize::MAX;
+
+/// sits at the start of every slab frame
+struct SlabHeader
+{
+    cache: usize,
+    /// address of the first free object, 0 when the slab is full
+    free: usize,
+    inuse: usize,
+    /// neighbours in the partial list of the cache, as ppns
+    prev: usize,
+    next: usize,
+}
+
+/// objects of one layout carved from single frames, unless it has a constructor the
+/// global allocator serves every allocation of that layout from it, so types that
+/// share a layout share a cache
+#[derive(Clone, Copy)]
+pub struct SlabCache
+{
+    name: &'static str,
+    size: usize,
+    align: usize,
+    stride: usize,
+    /// where the first object starts in a slab
+    offset: usize,
+    /// where the free list link sits in an object, past the object when there is a
+    /// constructor so free objects stay constructed
+    link: usize,
+    per_slab: usize,
+    ctor: Option<fn(*mut u8)>,
+    /// slabs with at least one free object
+    partial: usize,
+    slabs: usize,
+    active: usize,
+    allocs: usize,
+    frees: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SlabStats
+{
+    pub name: &'static str,
+    pub size: usize,
+    pub per_slab: usize,
+    pub slabs: usize,
+    /// objects handed out right now
+    pub active: usize,
+    pub allocs: usize,
+    pub frees: usize,
+}
+
+fn round_up(v: usize, align: usize) -> usize
+{
+    (v + align - 1) & !(align - 1)
+}
+
+fn header(ppn: usize) -> &'static mut SlabHeader
+{
+    PhyAddr::from(ppn * PAGE_SIZE).get_mut()
+}
+
+impl SlabCache
+{
+    fn new(name: &'static str, layout: Layout, ctor: Option<fn(*mut u8)>) -> Option<Self>
+    {
+        let align = layout.align().max(core::mem::size_of::<usize>());
+        let link = if ctor.is_some() { round_up(layout.size(), core::mem::size_of::<usize>()) } else { 0 };
+        let stride = round_up(link + core::mem::size_of::<usize>(), align).max(round_up(layout.size(), align));
+        let offset = round_up(core::mem::size_of::<SlabHeader>(), align);
+        if offset >= PAGE_SIZE || (PAGE_SIZE - offset) / stride < MIN_OBJECTS
+        {
+            return None;
+        }
+        Some(SlabCache
+        {
+            name,
+            size: layout.size(),
+            align: layout.align(),
+            stride,
+            offset,
+            link,
+            per_slab: (PAGE_SIZE - offset) / stride,
+            ctor,
+            partial: NIL,
+            slabs: 0,
+            active: 0,
+            allocs: 0,
+            frees: 0,
+        })
+    }
+
+    fn serves(&self, layout: Layout) -> bool
+    {
+        self.size == layout.size() && self.align == layout.align()
+    }
+
+    fn next_free(&self, obj: usize) -> &'static mut usize
+    {
+        PhyAddr::from(obj + self.link).get_mut()
+    }
+
+    fn link_partial(&mut self, ppn: usize)
+    {
+        let head = self.partial;
+        let slab = header(ppn);
+        slab.prev = NIL;
+        slab.next = head;
+        if head != NIL
+        {
+            header(head).prev = ppn;
+        }
+        self.partial = ppn;
+    }
+
+    fn unlink_partial(&mut self, ppn: usize)
+    {
+        let slab = header(ppn);
+        if slab.prev != NIL
+        {
+            header(slab.prev).next = slab.next;
+        }
+        else
+        {
+            self.partial = slab.next;
+        }
+        if slab.next != NIL
+        {
+            header(slab.next).prev = slab.prev;
+        }
+    }
+
+    /// take a frame and construct all of its objects
+    fn grow(&mut self, id: usize) -> bool
+    {
+        let ppn = match alloc_slab_frame()
+        {
+            Some(ppn) => usize::from(ppn),
+            None => return false,
+        };
+        let base = ppn * PAGE_SIZE;
+        let mut free = 0;
+        for i in (0..self.per_slab).rev()
+        {
+            let obj = base + self.offset + i * self.stride;
+            if let Some(ctor) = self.ctor
+            {
+                ctor(obj as *mut u8);
+            }
+            *self.next_free(obj) = free;
+            free = obj;
+        }
+        *header(ppn) = SlabHeader { cache: id, free, inuse: 0, prev: NIL, next: NIL };
+        self.link_partial(ppn);
+        self.slabs += 1;
+        true
+    }
+
+    fn alloc(&mut self, id: usize) -> Option<usize>
+    {
+        if self.partial == NIL && !self.grow(id)
+        {
+            return None;
+        }
+        let ppn = self.partial;
+        let slab = header(ppn);
+        let obj = slab.free;
+        slab.free = *self.next_free(obj);
+        slab.inuse += 1;
+        if slab.free == 0
+        {
+            self.unlink_partial(ppn);
+        }
+        self.active += 1;
+        self.allocs += 1;
+        Some(obj)
+    }
+
+    /// objects go back constructed, a slab left empty is returned to the frame
+    /// allocator unless it is the only one with room
+    fn dealloc(&mut self, obj: usize)
+    {
+        let ppn = obj / PAGE_SIZE;
+        let slab = header(ppn);
+        let was_full = slab.free == 0;
+        *self.next_free(obj) = slab.free;
+        slab.free = obj;
+        slab.inuse -= 1;
+        if was_full
+        {
+            self.link_partial(ppn);
+        }
+        self.active -= 1;
+        self.frees += 1;
+        if slab.inuse == 0 && (slab.prev != NIL || slab.next != NIL)
+        {
+            self.unlink_partial(ppn);
+            self.slabs -= 1;
+            dealloc_slab_frame(PhyPage::from(ppn));
+        }
+    }
+
+    fn stats(&self) -> SlabStats
+    {
+        SlabStats
+        {
+            name: self.name,
+            size: self.size,
+            per_slab: self.per_slab,
+            slabs: self.slabs,
+            active: self.active,
+            allocs: self.allocs,
+            frees: self.frees,
+        }
+    }
+}
+
+/// the caches live in a fixed table, nothing here may use the heap
+pub struct SlabAllocator
+{
+    caches: [Option<SlabCache>; MAX_CACHES],
+}
+
+impl SlabAllocator
+{
+    fn new() -> Self
+    {
+        SlabAllocator
+        {
+            caches: [None; MAX_CACHES],
+        }
+    }
+
+    /// the cache for layout and ctor, the global allocator only uses the ones without a constructor
+    fn find(&self, layout: Layout, ctor: Option<fn(*mut u8)>) -> Option<usize>
+    {
+        let ctor = ctor.map(|f| f as usize);
+        self.caches.iter().position(|cache| cache.map_or(false, |cache| {
+            cache.serves(layout) && cache.ctor.map(|f| f as usize) == ctor
+        }))
+    }
+}
+
+lazy_static!
+{
+    pub static ref SLAB_ALLOCATOR: up_safe_cell<SlabAllocator> = unsafe { up_safe_cell::new(SlabAllocator::new()) };
+}
+
+/// a cache for layout, or the one already there with the same constructor,
+/// None if the table is full or the objects are too big for a slab
+pub fn register(name: &'static str, layout: Layout, ctor: Option<fn(*mut u8)>) -> Option<usize>
+{
+    let mut slab = SLAB_ALLOCATOR.access();
+    if let Some(id) = slab.find(layout, ctor)
+    {
+        return Some(id);
+    }
+    let cache = SlabCache::new(name, layout, ctor)?;
+    let id = slab.caches.iter().position(|cache| cache.is_none())?;
+    slab.caches[id] = Some(cache);
+    Some(id)
+}
+
+/// the block Arc::new allocates for a T, the counts come before the value
+pub fn arc_layout<T>() -> Layout
+{
+    Layout::new::<[usize; 2]>().extend(Layout::new::<T>()).unwrap().0.pad_to_align()
+}
+
+pub fn register_arc<T>(name: &'static str) -> Option<usize>
+{
+    register(name, arc_layout::<T>(), None)
+}
+
+pub fn register_box<T>(name: &'static str) -> Option<usize>
+{
+    register(name, Layout::new::<T>(), None)
+}
+
+/// an object from cache id, in the state its constructor left it
+pub fn cache_alloc(id: usize) -> Option<*mut u8>
+{
+    let mut slab = SLAB_ALLOCATOR.access();
+    slab.caches[id].as_mut().unwrap().alloc(id).map(|obj| obj as *mut u8)
+}
+
+/// give back an object from cache_alloc, it must be back in its constructed state
+pub fn cache_free(id: usize, ptr: *mut u8)
+{
+    let mut slab = SLAB_ALLOCATOR.access();
+    slab.caches[id].as_mut().unwrap().dealloc(ptr as usize);
+}
+
+/// for the global allocator, None if no cache serves layout or it has no frame to grow
+pub fn alloc(layout: Layout) -> Option<*mut u8>
+{
+    let mut slab = SLAB_ALLOCATOR.access();
+    let id = slab.find(layout, None)?;
+    slab.caches[id].as_mut().unwrap().alloc(id).map(|obj| obj as *mut u8)
+}
+
+/// for the global allocator, false if ptr did not come from a slab, as blocks of a
+/// layout allocated before its cache existed or while it had no frames sit in the heap
+pub fn dealloc(ptr: *mut u8, layout: Layout) -> bool
+{
+    let mut slab = SLAB_ALLOCATOR.access();
+    let id = match slab.find(layout, None)
+    {
+        Some(id) => id,
+        None => return false,
+    };
+    let ppn = ptr as usize / PAGE_SIZE;
+    if !is_slab_frame(PhyPage::from(ppn)) || header(ppn).cache != id
+    {
+        return false;
+    }
+    slab.caches[id].as_mut().unwrap().dealloc(ptr as usize);
+    true
+}
+
+pub fn slab_stats() -> Vec<SlabStats>
+{
+    // copied out first, the Vec may itself come from a slab
+    let caches = SLAB_ALLOCATOR.access().caches;
+    caches.iter().flatten().map(|cache| cache.stats()).collect()
+}
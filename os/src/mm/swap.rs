@@ -0,0 +1,160 @@
+extern crate alloc;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use crate::config::{PAGE_SIZE, VIRTIO0};
+use crate::drivers::{VirtioBlk, SECTOR_SIZE};
+use crate::sync::up_safe_cell;
+use super::PhyPage;
+
+const SECTORS_PER_SLOT : usize = PAGE_SIZE / SECTOR_SIZE;
+
+/// a page worth of swap space, every address space that maps the page
+/// holds an Arc to it and the slot is given back with the last one
+pub struct SwapSlot
+{
+    pub slot: usize,
+}
+
+impl SwapSlot
+{
+    pub fn slot(&self) -> usize
+    {
+        self.slot
+    }
+}
+
+impl Drop for SwapSlot
+{
+    fn drop(&mut self)
+    {
+        SWAP_MANAGER.access().dealloc(self.slot);
+    }
+}
+
+/// hands out the slots of the whole disk, a set bit in used marks a slot in use
+pub struct SwapManager
+{
+    device: Option<VirtioBlk>,
+    used: Vec<u64>,
+    end: usize,
+    free: usize,
+    /// the word of used where the search for a free slot starts
+    next: usize,
+}
+
+impl SwapManager
+{
+    fn new() -> Self
+    {
+        SwapManager
+        {
+            device: None,
+            used: Vec::new(),
+            end: 0,
+            free: 0,
+            next: 0,
+        }
+    }
+
+    fn set_slots(&mut self, end: usize)
+    {
+        self.used = vec![0; (end + 63) / 64];
+        // the bits past end are never free
+        if end % 64 != 0
+        {
+            *self.used.last_mut().unwrap() = !0 << (end % 64);
+        }
+        self.end = end;
+        self.free = end;
+        self.next = 0;
+    }
+
+    fn alloc(&mut self) -> Option<usize>
+    {
+        if self.free == 0
+        {
+            return None;
+        }
+        let words = self.used.len();
+        let word = (0..words).map(|i| (self.next + i) % words).find(|i| self.used[*i] != !0)?;
+        let bit = (!self.used[word]).trailing_zeros() as usize;
+        self.used[word] |= 1 << bit;
+        self.free -= 1;
+        self.next = word;
+        Some(word * 64 + bit)
+    }
+
+    fn dealloc(&mut self, slot: usize)
+    {
+        let (word, bit) = (slot / 64, slot % 64);
+        if slot >= self.end || self.used[word] & (1 << bit) == 0
+        {
+            panic!("swap slot {} is not in use", slot);
+        }
+        self.used[word] &= !(1 << bit);
+        self.free += 1;
+    }
+
+    fn free(&self) -> usize
+    {
+        self.free
+    }
+}
+
+lazy_static!
+{
+    pub static ref SWAP_MANAGER: up_safe_cell<SwapManager> =
+    unsafe
+    {
+        up_safe_cell::new(SwapManager::new())
+    };
+}
+
+/// use the virtio-blk disk at VIRTIO0 as swap, if there is one
+pub fn init()
+{
+    match VirtioBlk::new(VIRTIO0)
+    {
+        Some(device) =>
+        {
+            let mut swap = SWAP_MANAGER.access();
+            swap.set_slots(device.capacity() / SECTORS_PER_SLOT);
+            swap.device = Some(device);
+            println!("swap: {} slots on virtio-blk", swap.end);
+        },
+        None =>
+        {
+            println!("swap: no virtio-blk disk, swapping is off");
+        },
+    }
+}
+
+/// whether swap_out has a chance to succeed
+pub fn swap_available() -> bool
+{
+    SWAP_MANAGER.access().free() != 0
+}
+
+/// write the frame out to a fresh slot
+pub fn swap_out(ppn: PhyPage) -> Option<SwapSlot>
+{
+    let mut swap = SWAP_MANAGER.access();
+    let slot = swap.alloc()?;
+    if !swap.device.as_mut().unwrap().write(slot * SECTORS_PER_SLOT, ppn.as_bytes())
+    {
+        swap.dealloc(slot);
+        return None;
+    }
+    Some(SwapSlot { slot })
+}
+
+/// read the page in slot into the frame, the slot stays allocated
+pub fn swap_in(slot: &SwapSlot, ppn: PhyPage)
+{
+    let mut swap = SWAP_MANAGER.access();
+    if !swap.device.as_mut().unwrap().read(slot.slot() * SECTORS_PER_SLOT, ppn.as_bytes())
+    {
+        panic!("swap: reading slot {} failed", slot.slot());
+    }
+}
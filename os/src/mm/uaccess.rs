@@ -0,0 +1,182 @@
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::{size_of, MaybeUninit};
+use crate::config::USERSTACK_TOP;
+use crate::task::{handle_page_fault, with_current_mem};
+use super::{VirAddr, VirPage};
+use super::pagetable::PteFlags;
+
+const EFAULT : isize = 14;
+const ENAMETOOLONG : isize = 36;
+const E2BIG : isize = 7;
+
+/// longest string read_user_cstr takes, the nul included
+pub const USER_CSTR_MAX : usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserError
+{
+    /// part of the range is outside user space or can't be accessed that way
+    Fault,
+    /// no nul within the limit
+    TooLong,
+    /// a string vector larger than the space left for it
+    TooBig,
+}
+
+impl UserError
+{
+    /// the negative errno a syscall returns for it
+    pub fn errno(self) -> isize
+    {
+        match self
+        {
+            UserError::Fault => -EFAULT,
+            UserError::TooLong => -ENAMETOOLONG,
+            UserError::TooBig => -E2BIG,
+        }
+    }
+}
+
+/// make the page of va present and check that user mode may read it, or write it if write,
+/// the kernel view of [va, end of page) is only good until the next fault or switch
+fn user_page(va: usize, write: bool) -> Result<&'static mut [u8], UserError>
+{
+    if va >= USERSTACK_TOP
+    {
+        return Err(UserError::Fault);
+    }
+    // brings in lazy and swapped pages and breaks copy-on-write for stores
+    handle_page_fault(va, write).map_err(|_| UserError::Fault)?;
+    with_current_mem(|mem| {
+        let pte = match mem.pgt.find(VirPage::from(VirAddr::from(va)))
+        {
+            Some(pte) if pte.valid() => pte,
+            _ => return Err(UserError::Fault),
+        };
+        let needed = PteFlags::U | if write { PteFlags::W } else { PteFlags::R };
+        if !pte.flags().contains(needed)
+        {
+            return Err(UserError::Fault);
+        }
+        let offset = VirAddr::from(va).offset();
+        Ok(&mut pte.ppn().as_bytes()[offset..])
+    })
+}
+
+/// hand each page-sized piece of [start, start + len) to f along with its offset,
+/// a piece is checked right before f runs so nothing can swap it out in between
+fn for_each_user_chunk(start: usize, len: usize, write: bool, mut f: impl FnMut(&mut [u8], usize)) -> Result<(), UserError>
+{
+    let end = start.checked_add(len).ok_or(UserError::Fault)?;
+    if end > USERSTACK_TOP
+    {
+        return Err(UserError::Fault);
+    }
+    let mut cur = start;
+    while cur < end
+    {
+        let page = user_page(cur, write)?;
+        let chunk = page.len().min(end - cur);
+        f(&mut page[..chunk], cur - start);
+        cur += chunk;
+    }
+    Ok(())
+}
+
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> Result<(), UserError>
+{
+    for_each_user_chunk(src, dst.len(), false, |chunk, offset| {
+        dst[offset..offset + chunk.len()].copy_from_slice(chunk);
+    })
+}
+
+pub fn copy_to_user(dst: usize, src: &[u8]) -> Result<(), UserError>
+{
+    for_each_user_chunk(dst, src.len(), true, |chunk, offset| {
+        chunk.copy_from_slice(&src[offset..offset + chunk.len()]);
+    })
+}
+
+/// T must be plain old data that any bit pattern is valid for
+pub fn read_user<T: Copy>(src: usize) -> Result<T, UserError>
+{
+    let mut val = MaybeUninit::<T>::uninit();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    copy_from_user(bytes, src)?;
+    Ok(unsafe { val.assume_init() })
+}
+
+pub fn write_user<T: Copy>(dst: usize, val: &T) -> Result<(), UserError>
+{
+    let bytes = unsafe { core::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(dst, bytes)
+}
+
+/// the nul terminated string at src, at most max bytes with the nul,
+/// bytes that aren't utf-8 come out as U+FFFD
+pub fn read_user_cstr(src: usize, max: usize) -> Result<String, UserError>
+{
+    let mut bytes = Vec::new();
+    let mut cur = src;
+    loop
+    {
+        let page = user_page(cur, false)?;
+        match page.iter().position(|ch| *ch == 0)
+        {
+            Some(nul) =>
+            {
+                if bytes.len() + nul >= max
+                {
+                    return Err(UserError::TooLong);
+                }
+                bytes.extend_from_slice(&page[..nul]);
+                break;
+            },
+            None =>
+            {
+                bytes.extend_from_slice(page);
+                if bytes.len() >= max
+                {
+                    return Err(UserError::TooLong);
+                }
+                cur = cur.checked_add(page.len()).ok_or(UserError::Fault)?;
+            },
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// the strings of the null terminated array of string pointers at src, which may be 0 for
+/// none, each takes its bytes, its nul and its pointer out of budget
+pub fn read_user_strv(src: usize, budget: &mut usize) -> Result<Vec<String>, UserError>
+{
+    let mut strings = Vec::new();
+    if src == 0
+    {
+        return Ok(strings);
+    }
+    let mut cur = src;
+    loop
+    {
+        let ptr = read_user::<usize>(cur)?;
+        if ptr == 0
+        {
+            return Ok(strings);
+        }
+        let string = read_user_cstr(ptr, (*budget).min(USER_CSTR_MAX)).map_err(|err| match err
+        {
+            UserError::TooLong => UserError::TooBig,
+            err => err,
+        })?;
+        let cost = string.len() + 1 + size_of::<usize>();
+        if cost > *budget
+        {
+            return Err(UserError::TooBig);
+        }
+        *budget -= cost;
+        strings.push(string);
+        cur = cur.checked_add(size_of::<usize>()).ok_or(UserError::Fault)?;
+    }
+}
@@ -1,28 +1,33 @@
-extern crate alloc;
-use alloc::vec::Vec;
-use crate::sbi::console_getchar;
-use crate::mm::get_arr_from_userspace;
-use crate::task::{get_current_task_satp, suspend};
+use crate::config::USERSTACK_TOP;
+use crate::sbi::{console_getchar, console_putchar};
+use crate::mm::{copy_from_user, copy_to_user, UserError};
+use crate::task::suspend;
 
 const STDOUT : usize = 1;
 const STDIN : usize = 0;
 
+/// bytes of a user buffer sys_read and sys_write move at a time
+const IO_CHUNK : usize = 256;
+
 pub fn sys_read(fd : usize, buf:*mut u8, len: usize) -> isize
 {
     match fd
     {
         STDIN =>
         {
-            let satp = get_current_task_satp();
-            let mut segments : Vec<&'static mut [u8]> = get_arr_from_userspace(satp, buf as usize, len);
-            let mut _len = len;
-            let mut cur_seg_index = 0;
-            
-            while cur_seg_index < segments.len()
+            match (buf as usize).checked_add(len)
+            {
+                Some(end) if end <= USERSTACK_TOP => {},
+                _ => return UserError::Fault.errno(),
+            }
+            // read into the kernel first, the user pages may be swapped out while we wait
+            let mut chunk = [0u8; IO_CHUNK];
+            let mut done = 0;
+            while done < len
             {
+                let n = (len - done).min(IO_CHUNK);
                 let mut i = 0;
-                let cur_seg = &mut segments[cur_seg_index];
-                while i < cur_seg.len()
+                while i < n
                 {
                     let c = console_getchar();
                     if c == 0
@@ -30,15 +35,16 @@ pub fn sys_read(fd : usize, buf:*mut u8, len: usize) -> isize
                         suspend();
                         continue;
                     }
-                    else
-                    {
-                        cur_seg[i] = c as u8;
-                        i += 1;
-                    }
+                    chunk[i] = c as u8;
+                    i += 1;
+                }
+                if let Err(err) = copy_to_user(buf as usize + done, &chunk[..n])
+                {
+                    return err.errno();
                 }
-                cur_seg_index += 1;
+                done += n;
             }
-            1
+            len as isize
         }
         _ =>
         {
@@ -53,12 +59,24 @@ pub fn sys_write(fd : usize, buf: * const u8, len: usize) -> isize
     {
         STDOUT =>
         {
-            let satp  =get_current_task_satp();
-            let slices = get_arr_from_userspace(satp, buf as usize, len);
-            for slice in slices
+            if (buf as usize).checked_add(len).is_none()
+            {
+                return UserError::Fault.errno();
+            }
+            let mut chunk = [0u8; IO_CHUNK];
+            let mut done = 0;
+            while done < len
             {
-                let str = core::str::from_utf8(slice).unwrap();
-                print!("{}", str);
+                let n = (len - done).min(IO_CHUNK);
+                if let Err(err) = copy_from_user(&mut chunk[..n], buf as usize + done)
+                {
+                    return err.errno();
+                }
+                for ch in chunk[..n].iter()
+                {
+                    console_putchar(*ch as usize);
+                }
+                done += n;
             }
             len as isize
         },
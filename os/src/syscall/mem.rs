@@ -0,0 +1,242 @@
+extern crate alloc;
+use alloc::vec::Vec;
+use crate::config::{PAGE_SIZE, PAGE_BITS, PAGE_LEVELS, MMAP_TOP, USERSTACK_TOP};
+use crate::mm::{MemPermit, MapType, AreaInfo, VirAddr, VirPage, write_user};
+use crate::task::with_current_mem;
+
+const PROT_READ : usize = 1;
+const PROT_WRITE : usize = 2;
+const PROT_EXEC : usize = 4;
+
+const MAP_SHARED : usize = 0x01;
+const MAP_PRIVATE : usize = 0x02;
+const MAP_FIXED : usize = 0x10;
+const MAP_ANONYMOUS : usize = 0x20;
+
+fn prot_to_permit(prot: usize) -> MemPermit
+{
+    let mut permit = MemPermit::empty();
+    if prot & PROT_READ != 0
+    {
+        permit |= MemPermit::R;
+    }
+    if prot & PROT_WRITE != 0
+    {
+        // riscv has no write-only pages
+        permit |= MemPermit::R | MemPermit::W;
+    }
+    if prot & PROT_EXEC != 0
+    {
+        permit |= MemPermit::X;
+    }
+    permit
+}
+
+fn permit_to_prot(permit: MemPermit) -> usize
+{
+    let mut prot = 0;
+    if permit.contains(MemPermit::R)
+    {
+        prot |= PROT_READ;
+    }
+    if permit.contains(MemPermit::W)
+    {
+        prot |= PROT_WRITE;
+    }
+    if permit.contains(MemPermit::X)
+    {
+        prot |= PROT_EXEC;
+    }
+    prot
+}
+
+/// an area as sys_vmas hands it out
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Vma
+{
+    start: usize,
+    end: usize,
+    prot: usize,
+    /// 0 identical, 1 framed, 2 lazy, 3 shared
+    kind: usize,
+    resident: usize,
+    swapped: usize,
+}
+
+impl From<AreaInfo> for Vma
+{
+    fn from(info: AreaInfo) -> Self
+    {
+        Vma
+        {
+            start: info.start.into(),
+            end: info.end.into(),
+            prot: permit_to_prot(info.permit),
+            kind: match info.map_type
+            {
+                MapType::Indentical => 0,
+                MapType::Framed => 1,
+                MapType::Lazy => 2,
+                MapType::Shared => 3,
+            },
+            resident: info.resident,
+            swapped: info.swapped,
+        }
+    }
+}
+
+/// what sys_pagewalk hands out, ptes past levels are 0 and pa is 0 when nothing is mapped
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Walk
+{
+    levels: usize,
+    ptes: [usize; 4],
+    pa: usize,
+}
+
+fn len_to_pages(len: usize) -> Option<usize>
+{
+    if len == 0
+    {
+        return None;
+    }
+    Some(len.checked_add(PAGE_SIZE - 1)? >> PAGE_BITS)
+}
+
+/// whether pages starting at the page-aligned start stay inside the user mmap range
+fn in_user_range(start: usize, pages: usize) -> bool
+{
+    let end = pages.checked_mul(PAGE_SIZE).and_then(|len| start.checked_add(len));
+    start != 0 && end.map_or(false, |end| end <= MMAP_TOP)
+}
+
+pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: isize, offset: usize) -> isize
+{
+    // only private anonymous memory for now
+    if flags & MAP_ANONYMOUS == 0 || flags & MAP_SHARED != 0 || flags & MAP_PRIVATE == 0 || fd != -1 || offset != 0
+    {
+        return -1;
+    }
+    let fixed = flags & MAP_FIXED != 0;
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 || (fixed && addr % PAGE_SIZE != 0)
+    {
+        return -1;
+    }
+    let pages = match len_to_pages(len)
+    {
+        Some(pages) => pages,
+        None => return -1,
+    };
+    let hint = addr & !(PAGE_SIZE - 1);
+    let usable = in_user_range(hint, pages);
+    if fixed && !usable
+    {
+        return -1;
+    }
+    let hint = if usable { hint } else { 0 };
+
+    let permit = prot_to_permit(prot);
+    match with_current_mem(|mem| mem.mmap(VirPage::from(VirAddr::from(hint)), pages, permit, fixed))
+    {
+        Some(start) => usize::from(VirAddr::from(start)) as isize,
+        None => -1,
+    }
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize
+{
+    if addr % PAGE_SIZE != 0
+    {
+        return -1;
+    }
+    let pages = match len_to_pages(len)
+    {
+        Some(pages) if in_user_range(addr, pages) => pages,
+        _ => return -1,
+    };
+    let start = VirPage::from(VirAddr::from(addr));
+    with_current_mem(|mem| mem.munmap(start, start.add(pages)));
+    0
+}
+
+/// brk(0) only queries the break, like linux a failed move returns the old one
+pub fn sys_brk(addr: usize) -> isize
+{
+    with_current_mem(|mem|
+        if addr == 0
+        {
+            mem.brk
+        }
+        else
+        {
+            mem.set_brk(addr)
+        }
+    ) as isize
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize
+{
+    if addr % PAGE_SIZE != 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0
+    {
+        return -1;
+    }
+    let pages = match len_to_pages(len)
+    {
+        Some(pages) => pages,
+        None => return -1,
+    };
+    // unlike mmap this may reach the program and the stack
+    match pages.checked_mul(PAGE_SIZE).and_then(|len| addr.checked_add(len))
+    {
+        Some(end) if end <= USERSTACK_TOP => {},
+        _ => return -1,
+    }
+    let start = VirPage::from(VirAddr::from(addr));
+    let permit = prot_to_permit(prot) | MemPermit::U;
+    if with_current_mem(|mem| mem.mprotect(start, start.add(pages), permit))
+    {
+        0
+    }
+    else
+    {
+        -1
+    }
+}
+
+/// fill buf with up to count areas of the address space in address order,
+/// returns how many areas there are in all
+pub fn sys_vmas(buf: usize, count: usize) -> isize
+{
+    let vmas: Vec<Vma> = with_current_mem(|mem| mem.areas_info()).into_iter().map(Vma::from).collect();
+    for (i, vma) in vmas.iter().take(count).enumerate()
+    {
+        if let Err(err) = write_user(buf + i * core::mem::size_of::<Vma>(), vma)
+        {
+            return err.errno();
+        }
+    }
+    vmas.len() as isize
+}
+
+/// the page table entries translating va goes through, returns how many levels were walked
+pub fn sys_pagewalk(va: usize, buf: usize) -> isize
+{
+    let walk = with_current_mem(|mem| mem.walk(VirAddr::from(va)));
+    let mut out = Walk
+    {
+        levels: walk.levels,
+        ptes: [0; 4],
+        pa: walk.pa.map_or(0, usize::from),
+    };
+    for level in 0..walk.levels.min(PAGE_LEVELS)
+    {
+        out.ptes[level] = walk.ptes[level].entry;
+    }
+    match write_user(buf, &out)
+    {
+        Ok(()) => walk.levels as isize,
+        Err(err) => err.errno(),
+    }
+}
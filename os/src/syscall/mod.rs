@@ -1,23 +1,40 @@
 mod proc;
 mod fs;
+mod mem;
+mod shm;
 use fs::sys_write;
-use crate::task::{exit as sys_exit, suspend as sys_yield, wait, fork, exec};
+use crate::task::{exit as sys_exit, suspend as sys_yield, wait, fork};
 use crate::timer::get_time_in_ms;
-use crate::mm::get_str_from_userspace;
 
 use self::fs::sys_read;
+use self::mem::{sys_mmap, sys_munmap, sys_brk, sys_mprotect, sys_vmas, sys_pagewalk};
+use self::proc::{sys_getrlimit, sys_setrlimit, sys_exec};
+use self::shm::{sys_shmget, sys_shmctl, sys_shmat, sys_shmdt};
 
 const READ : usize = 63;
 const WRITE : usize = 64;
 const EXIT : usize = 93;
 const YIELD : usize = 124;
+const GETRLIMIT : usize = 163;
+const SETRLIMIT : usize = 164;
 const GET_TIME : usize = 169;
 const GET_PID : usize = 172;
+const SHMGET : usize = 194;
+const SHMCTL : usize = 195;
+const SHMAT : usize = 196;
+const SHMDT : usize = 197;
 const FORK : usize = 220;
 const EXEC : usize = 221;
+const BRK : usize = 214;
+const MUNMAP : usize = 215;
+const MMAP : usize = 222;
+const MPROTECT : usize = 226;
 const WAIT_PID : usize = 260;
+// not in linux, numbered past its range
+const VMAS : usize = 1000;
+const PAGE_WALK : usize = 1001;
 
-pub fn sys_call(call_num: usize, args: [usize; 3]) -> isize
+pub fn sys_call(call_num: usize, args: [usize; 6]) -> isize
 {
     match call_num
     {
@@ -48,13 +65,59 @@ pub fn sys_call(call_num: usize, args: [usize; 3]) -> isize
         },
         FORK =>
         {
-            fork() as isize
+            fork()
         },
         EXEC =>
         {
-            let app_name = get_str_from_userspace(args[0]);
-            exec(&app_name);
-            0
+            sys_exec(args[0], args[1], args[2])
+        },
+        MMAP =>
+        {
+            sys_mmap(args[0], args[1], args[2], args[3], args[4] as isize, args[5])
+        },
+        MUNMAP =>
+        {
+            sys_munmap(args[0], args[1])
+        },
+        BRK =>
+        {
+            sys_brk(args[0])
+        },
+        MPROTECT =>
+        {
+            sys_mprotect(args[0], args[1], args[2])
+        },
+        GETRLIMIT =>
+        {
+            sys_getrlimit(args[0], args[1])
+        },
+        SETRLIMIT =>
+        {
+            sys_setrlimit(args[0], args[1])
+        },
+        SHMGET =>
+        {
+            sys_shmget(args[0], args[1], args[2])
+        },
+        SHMCTL =>
+        {
+            sys_shmctl(args[0], args[1], args[2])
+        },
+        SHMAT =>
+        {
+            sys_shmat(args[0], args[1], args[2])
+        },
+        SHMDT =>
+        {
+            sys_shmdt(args[0])
+        },
+        VMAS =>
+        {
+            sys_vmas(args[0], args[1])
+        },
+        PAGE_WALK =>
+        {
+            sys_pagewalk(args[0], args[1])
         },
         _ =>
         {
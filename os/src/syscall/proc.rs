@@ -12,3 +12,77 @@ pub fn sys_yield()
 }
 */
 
+
+use crate::config::{PAGE_SIZE, ARG_MAX};
+use crate::mm::{read_user, write_user, read_user_cstr, read_user_strv, USER_CSTR_MAX};
+use crate::task::{with_current_mem, exec};
+
+const RLIMIT_RSS : usize = 5;
+const RLIM_INFINITY : usize = usize::MAX;
+
+/// only RLIMIT_RSS is supported, the hard limit always reads back as infinite
+pub fn sys_getrlimit(resource: usize, rlim: usize) -> isize
+{
+    if resource != RLIMIT_RSS
+    {
+        return -1;
+    }
+    let cur = match with_current_mem(|mem| mem.frame_limit)
+    {
+        Some(frames) => frames * PAGE_SIZE,
+        None => RLIM_INFINITY,
+    };
+    // struct rlimit { rlim_cur, rlim_max }
+    match write_user(rlim, &[cur, RLIM_INFINITY])
+    {
+        Ok(()) => 0,
+        Err(err) => err.errno(),
+    }
+}
+
+/// the soft RLIMIT_RSS caps the frames of the address space, rounded down to whole frames
+pub fn sys_setrlimit(resource: usize, rlim: usize) -> isize
+{
+    if resource != RLIMIT_RSS
+    {
+        return -1;
+    }
+    let [cur, max] = match read_user::<[usize; 2]>(rlim)
+    {
+        Ok(limits) => limits,
+        Err(err) => return err.errno(),
+    };
+    if cur > max
+    {
+        return -1;
+    }
+    let limit = if cur == RLIM_INFINITY { None } else { Some(cur / PAGE_SIZE) };
+    with_current_mem(|mem| mem.frame_limit = limit);
+    0
+}
+
+/// argv and envp are null terminated arrays of string pointers, either may be 0 for an empty
+/// one, with no argv at all the program gets the app name as its only argument
+pub fn sys_exec(path: usize, argv: usize, envp: usize) -> isize
+{
+    let mut budget = ARG_MAX;
+    let strings = read_user_cstr(path, USER_CSTR_MAX).and_then(|name| {
+        let args = read_user_strv(argv, &mut budget)?;
+        let envs = read_user_strv(envp, &mut budget)?;
+        Ok((name, args, envs))
+    });
+    let (name, mut args, envs) = match strings
+    {
+        Ok(strings) => strings,
+        Err(err) => return err.errno(),
+    };
+    if argv == 0
+    {
+        args.push(name.clone());
+    }
+    match exec(&name, args, envs)
+    {
+        Ok(()) => 0,
+        Err(err) => err.errno(),
+    }
+}
@@ -0,0 +1,99 @@
+use crate::config::{PAGE_SIZE, PAGE_BITS};
+use crate::mm::{MemPermit, VirAddr, VirPage, SHM_MANAGER};
+use crate::task::with_current_mem;
+
+const IPC_PRIVATE : usize = 0;
+const IPC_CREAT : usize = 0o1000;
+const IPC_EXCL : usize = 0o2000;
+const IPC_RMID : usize = 0;
+const SHM_RDONLY : usize = 0o10000;
+
+/// id of the segment under key, created if IPC_CREAT is given and there is none
+pub fn sys_shmget(key: usize, size: usize, flags: usize) -> isize
+{
+    let mut manager = SHM_MANAGER.access();
+    if let Some(id) = manager.find(key)
+    {
+        if flags & IPC_CREAT != 0 && flags & IPC_EXCL != 0
+        {
+            return -1;
+        }
+        if size > manager.get(id).unwrap().size
+        {
+            return -1;
+        }
+        return id as isize;
+    }
+    if key != IPC_PRIVATE && flags & IPC_CREAT == 0
+    {
+        return -1;
+    }
+    if size == 0
+    {
+        return -1;
+    }
+    let pages = match size.checked_add(PAGE_SIZE - 1)
+    {
+        Some(end) => end >> PAGE_BITS,
+        None => return -1,
+    };
+    match manager.create(key, size, pages)
+    {
+        Some(id) => id as isize,
+        None => -1,
+    }
+}
+
+/// map segment id at addr, or anywhere if addr is 0, and return where it went
+pub fn sys_shmat(id: usize, addr: usize, flags: usize) -> isize
+{
+    if addr % PAGE_SIZE != 0
+    {
+        return -1;
+    }
+    let permit = if flags & SHM_RDONLY != 0 { MemPermit::R } else { MemPermit::R | MemPermit::W };
+    let manager = SHM_MANAGER.access();
+    let seg = match manager.get(id)
+    {
+        Some(seg) => seg,
+        None => return -1,
+    };
+    match with_current_mem(|mem| mem.attach_shm(seg, VirPage::from(VirAddr::from(addr)), permit))
+    {
+        Ok(start) => usize::from(VirAddr::from(start)) as isize,
+        Err(_) => -1,
+    }
+}
+
+pub fn sys_shmdt(addr: usize) -> isize
+{
+    if addr % PAGE_SIZE != 0
+    {
+        return -1;
+    }
+    if with_current_mem(|mem| mem.detach_shm(VirPage::from(VirAddr::from(addr))))
+    {
+        0
+    }
+    else
+    {
+        -1
+    }
+}
+
+/// only IPC_RMID, the frames go once the last attached address space lets go of them
+pub fn sys_shmctl(id: usize, cmd: usize, _buf: usize) -> isize
+{
+    if cmd != IPC_RMID
+    {
+        return -1;
+    }
+    if SHM_MANAGER.access().remove(id)
+    {
+        0
+    }
+    else
+    {
+        -1
+    }
+}
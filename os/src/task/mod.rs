@@ -2,4 +2,4 @@ mod task;
 mod task_context;
 mod pid;
 
-pub use task::{run, exit, suspend, get_current_task_ctx, get_current_task_satp, get_current_task_pgt, fork, exec, wait};
+pub use task::{init, run, dedup_tick, exit, suspend, get_current_task_ctx, get_current_task_satp, get_current_task_pgt, fork, exec, wait, handle_page_fault, with_current_mem, get_current_pid, get_current_name, is_stack_overflow, OOM_EXIT_CODE};
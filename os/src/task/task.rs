@@ -1,16 +1,18 @@
 extern crate alloc;
 use super::task_context::TaskContext;
-use crate::loader::get_app_by_name;
+use crate::loader::find_app_by_name;
 use crate::{config::*, loader};
 use crate::sync::up_safe_cell;
 use crate::trap::context::Context;
 use crate::trap::before_trapret;
 use alloc::collections::VecDeque;
 use alloc::vec::{Vec};
+use alloc::string::{String, ToString};
 use lazy_static::lazy_static;
 use core::arch::global_asm;
 use crate::config::PAGE_SIZE;
-use crate::mm::{MemorySet, to_prog, PhyPage, PhyAddr, VirPage, KERNEL_SPACE, FrameTracker, alloc, copy_page, VirAddr};
+use crate::mm::{MemorySet, MemError, ElfError, to_prog, init_stack, PhyPage, PhyAddr, VirPage, KERNEL_SPACE, FrameTracker, alloc, copy_page, VirAddr, frame_stats, write_user, register_arc, DedupTable};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use super::pid::{Pid, alloc as pid_alloc};
 use alloc::sync::{Arc, Weak};
 use crate::loader::get_index_by_name;
@@ -28,6 +30,9 @@ pub enum TaskStatus
     Exited,
 }
 
+/// exit code of a task killed because memory ran out or it went over its limit, as for SIGKILL
+pub const OOM_EXIT_CODE : isize = -9;
+
 pub struct TaskInner
 {
     pub ctx: TaskContext,
@@ -35,10 +40,11 @@ pub struct TaskInner
     pub mem: Option<MemorySet>,
     pub trapframe : FrameTracker,
     pub kernelstack_bot : FrameTracker,
-    pub userstack_bot : FrameTracker,
     pub parent: Option<Weak<Task>>,
     pub childrens: Vec<Arc<Task>>,
     pub exit_code: isize,
+    /// app the task runs, for reports
+    pub name: String,
 }
 
 pub struct Task
@@ -68,10 +74,10 @@ impl Task
                     mem: None,
                     trapframe: alloc().unwrap(),
                     kernelstack_bot: alloc().unwrap(),
-                    userstack_bot: alloc().unwrap(),
                     parent: None,
                     childrens: Vec::new(),
                     exit_code: 0,
+                    name: String::new(),
                 }
             )
         };
@@ -82,15 +88,19 @@ impl Task
         }
     }
 
-    pub fn fork_other(self: &Arc<Task>) -> Arc<Task>
+    /// frames of the address space plus the trapframe and kernel stack
+    pub fn resident_frames(&self) -> usize
+    {
+        let task_inner = self.task_inner.access();
+        task_inner.mem.as_ref().map_or(0, |mem| mem.resident_frames()) + 2
+    }
+
+    pub fn fork_other(self: &Arc<Task>) -> Result<Arc<Task>, MemError>
     {
-        let my_task_inner = self.task_inner.access();
+        let mut my_task_inner = self.task_inner.access();
 
-        let trapframe = alloc().unwrap();
-        let kernelstack_bot = alloc().unwrap();
-        let userstack_bot = alloc().unwrap();
-        // copy userstack
-        copy_page(userstack_bot.ppn, my_task_inner.userstack_bot.ppn);
+        let trapframe = alloc().ok_or(MemError::OutOfMemory)?;
+        let kernelstack_bot = alloc().ok_or(MemError::OutOfMemory)?;
 
         // init trapframe
         let src_ctx_ptr = usize::from(PhyAddr::from(my_task_inner.trapframe.ppn)) as *const Context;
@@ -104,11 +114,9 @@ impl Task
             dst_ctx.kernel_stack = usize::from(PhyAddr::from(kernelstack_bot.ppn)) + PAGE_SIZE;
        }
 
-        let mut mem_set = my_task_inner.mem.as_ref().unwrap().fork_one();
-        mem_set.map_trampoline();
-        mem_set.map_userstack(userstack_bot.ppn);
+        let mut mem_set = my_task_inner.mem.as_mut().unwrap().fork_one()?;
+        // the user stack is an area of mem_set and the trampoline is mapped by fork_one
         mem_set.map_trapframe(trapframe.ppn);
-        // initialze kernelstack userstack trapframe correctly
 
         let new_task_ctx = TaskContext::new(
             usize::from(PhyAddr::from(kernelstack_bot.ppn)) + PAGE_SIZE,
@@ -127,42 +135,49 @@ impl Task
                     mem: Some(mem_set),
                     trapframe,
                     kernelstack_bot,
-                    userstack_bot,
                     parent:Some(Arc::downgrade(self)),
                     childrens: Vec::new(),
                     exit_code: 0,
+                    name: my_task_inner.name.clone(),
                 }
             )
         };
 
-        Arc::new(
+        Ok(Arc::new(
         Task
         {
             task_inner,
             pid: pid_alloc(),
         }
-        )
+        ))
     }
 
-    pub fn exec(&self, app_name: &str)
+    /// the task keeps its old memory if the image can't be loaded
+    pub fn exec(&self, app_name: &str, args: &[String], envs: &[String]) -> Result<(), ElfError>
     {
         // set new memset trapframe
-        let (entry_point, memset) = to_prog(get_app_by_name(app_name));
+        let elf = find_app_by_name(app_name).ok_or(ElfError::NotFound)?;
+        let (entry_point, mut memset, auxv) = to_prog(elf)?;
         let mut task_inner = self.task_inner.access();
+        memset.frame_limit = task_inner.mem.as_ref().and_then(|mem| mem.frame_limit);
+
+        let user_stack_top = memset.map_userstack();
+        let user_sp = init_stack(&mut memset, user_stack_top, args, envs, &auxv)?;
         let old_memset = task_inner.mem.take();
-        
+        memset.map_trapframe(task_inner.trapframe.ppn());
         let trap_context_ptr = usize::from(PhyAddr::from(task_inner.trapframe.ppn())) as *mut Context;
-        let user_stack_top = usize::from(PhyAddr::from(task_inner.userstack_bot.ppn())) + PAGE_SIZE;
         
         unsafe
         {
             let mut trap_context = trap_context_ptr.as_mut().unwrap();
             trap_context.regs = [0;32];
             trap_context.sepc = entry_point;
-            trap_context.set_sp(user_stack_top);
+            trap_context.set_sp(user_sp);
         }
         task_inner.mem = Some(memset); 
+        task_inner.name = app_name.to_string();
         drop(old_memset);
+        Ok(())
     }
 
     pub fn get_ctx(&mut self) -> &mut Context
@@ -219,7 +234,7 @@ unsafe
            let app_total = unsafe { apps_ptr.read_volatile()}; 
 
            let mut init = Task::new_task();
-           init_task(get_index_by_name("init"), &mut init);
+           init_task(get_index_by_name("init").expect("no init app"), &mut init);
            let tmp = Arc::new(init);
            tasks.push_front(tmp.clone());
 
@@ -252,9 +267,14 @@ pub fn init_task(i:usize, task_out: &mut Task)
     let elf = loader::get_app(i);
     let entry_point: usize;
     let mut mem_set: MemorySet;
-    (entry_point, mem_set) = to_prog(elf);
-
-    let user_stack_va = mem_set.map_userstack(task.userstack_bot.ppn());
+    let auxv: Vec<(usize, usize)>;
+    (entry_point, mem_set, auxv) = to_prog(elf)
+        .unwrap_or_else(|err| panic!("can't load app {}: {:?}", loader::get_app_name(i), err));
+
+    let user_stack_top = mem_set.map_userstack();
+    let args = [loader::get_app_name(i).to_string()];
+    let user_stack_va = init_stack(&mut mem_set, user_stack_top, &args, &[], &auxv)
+        .unwrap_or_else(|err| panic!("can't set up the stack of app {}: {:?}", loader::get_app_name(i), err));
     mem_set.map_trapframe(task.trapframe.ppn());
     
     let context_ptr : *mut Context = usize::from(PhyAddr::from(task.trapframe.ppn())) as *mut Context;
@@ -268,6 +288,7 @@ pub fn init_task(i:usize, task_out: &mut Task)
     task.ctx.ra = kernel_ra;
     task.ctx.sp = kernel_stack_top as usize;
     task.mem = Some(mem_set);
+    task.name = loader::get_app_name(i).to_string();
     task.status = TaskStatus::Ready;
 }
 
@@ -283,7 +304,10 @@ impl TaskManager
         drop(other);
     }
 
-    pub fn wait(&self, pid: isize, user_addr: usize) -> isize
+    /// reap a zombie child matching pid (-1 for any), its (pid, exit code)
+    /// (pid, exit code) of a reaped child, Err(WAIT_NO_CHILD) if pid names no child
+    /// and Err(WAIT_RUNNING) if it does but none has exited yet
+    pub fn wait(&self, pid: isize) -> Result<(isize, isize), isize>
     {
         let task_manager = self.tasks.access();
         let cur_task = task_manager.get_cur_task().unwrap();
@@ -305,12 +329,16 @@ impl TaskManager
             let exit_code = zombie.task_inner.access().exit_code;
             let pid = zombie.pid.0;
             self.clean_up_exited_task(zombie);
-            *(task_inner.mem.as_ref().unwrap()
-                .pgt.translate_va(VirAddr::from(user_addr)).unwrap()
-                .get_mut()) = exit_code;
-            return pid
+            return Ok((pid, exit_code));
+        }
+        if task_inner.childrens.iter().any(|task| pid == -1 || task.pid.0 == pid)
+        {
+            Err(WAIT_RUNNING)
+        }
+        else
+        {
+            Err(WAIT_NO_CHILD)
         }
-        -1
     }
 
     pub fn suspend_cur_task(&self)
@@ -387,6 +415,40 @@ impl TaskManager
         }
     }
 
+    /// hand the children of task_inner over to init
+    fn reparent_children(&self, task_inner: &mut TaskInner)
+    {
+        let new_parent = self.init_task.access();
+        for child in task_inner.childrens.drain(..)
+        {
+            child.task_inner.access().parent = Some(Arc::downgrade(&*new_parent));
+            new_parent.task_inner.access().childrens.push(child);
+        }
+    }
+
+    /// the task other than init that holds the most frames
+    fn oom_victim(&self) -> Option<Arc<Task>>
+    {
+        let init_pid = self.init_task.access().pid.0;
+        let inner = self.tasks.access();
+        inner.cur.iter().chain(inner.tasks.iter())
+            .filter(|task| task.pid.0 != init_pid)
+            .max_by_key(|task| task.resident_frames())
+            .map(Arc::clone)
+    }
+
+    /// make a task that is waiting in the ready queue a zombie and free its address space,
+    /// the trapframe and kernel stack go when the parent reaps it
+    fn kill_task(&self, victim: &Arc<Task>, code: isize)
+    {
+        self.tasks.access().tasks.retain(|task| task.pid != victim.pid);
+        let mut victim_inner = victim.task_inner.access();
+        victim_inner.status = TaskStatus::Zombie;
+        victim_inner.exit_code = code;
+        victim_inner.mem = None;
+        self.reparent_children(&mut victim_inner);
+    }
+
     pub fn exit_cur_task(&self, code:isize) -> !
     {
         let cur_task = self.tasks.access().take_cur_task().unwrap();
@@ -394,22 +456,13 @@ impl TaskManager
 
         task_inner.status = TaskStatus::Zombie;
         task_inner.exit_code = code;
-        
-        let new_parent = self.init_task.access();
-
-        for i in (0..task_inner.childrens.len())
-        {
-            let child = &task_inner.childrens[i];
-            child.task_inner.access().parent = Some(Arc::downgrade(&*new_parent));
-            new_parent.task_inner.access().childrens.push(child.clone());
-        }
+        // we run on the kernel page table, the user pages can go now
+        task_inner.mem = None;
+        self.reparent_children(&mut task_inner);
 
         let loaded = &(self.tasks.access().kernel_ctx) as *const TaskContext;
         let saved = &mut task_inner.ctx as *mut TaskContext;
 
-        task_inner.childrens.clear();
-        
-        drop(new_parent);
         drop(task_inner);
         drop(cur_task);
 
@@ -431,32 +484,168 @@ pub fn suspend()
         TASK_MANAGER.access().suspend_cur_task();
 }
 
-pub fn fork() -> usize
+/// pid of the child, -1 if there is no memory for it even after the oom killer ran
+pub fn fork() -> isize
 {
-    let manager = TASK_MANAGER.access();
+    loop
+    {
+        let cur = TASK_MANAGER.access().tasks.access().get_cur_task().unwrap();
+        let new_task = match cur.fork_other()
+        {
+            Ok(task) => task,
+            Err(MemError::OutOfMemory) if swap_out_some() || oom_kill() => continue,
+            Err(_) => return -1,
+        };
 
-    let mut inner_manager = manager.tasks.access();
-    let cur = inner_manager.get_cur_task().unwrap();
+        new_task.task_inner.access().parent = Some(Arc::downgrade(&cur));
+        cur.task_inner.access().childrens.push(new_task.clone());
 
-    let new_task = cur.fork_other();
+        let pid = new_task.pid.0;
+        TASK_MANAGER.access().tasks.access().tasks.push_back(new_task);
+        return pid;
+    }
+}
 
-    new_task.task_inner.access().parent = Some(Arc::downgrade(&cur));
-    cur.task_inner.access().childrens.push(new_task.clone());
-    
-    let pid = new_task.pid.0 as usize;
-    inner_manager.tasks.push_back(new_task);
-    
-    pid
+/// swap a page out of some task, the ones waiting in the ready queue go
+/// before the current one, true if a frame was freed
+fn swap_out_some() -> bool
+{
+        let manager = TASK_MANAGER.access();
+        let inner = manager.tasks.access();
+        let tasks: Vec<Arc<Task>> = inner.tasks.iter().chain(inner.cur.iter()).map(Arc::clone).collect();
+        drop(inner);
+        drop(manager);
+        tasks.iter().any(|task| {
+            task.task_inner.access().mem.as_mut().map_or(false, |mem| mem.swap_out_one())
+        })
 }
 
-pub fn exec(app_name: &str)
+static TICKS : AtomicUsize = AtomicUsize::new(0);
+
+/// called on every timer tick, every DEDUP_INTERVAL ticks the read-only pages
+/// of all tasks with the same content are merged into one frame
+pub fn dedup_tick()
 {
-    return TASK_MANAGER.access().tasks.access().get_cur_task().unwrap().exec(app_name);        
+        let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+        if DEDUP_INTERVAL == 0 || ticks % DEDUP_INTERVAL != 0
+        {
+            return;
+        }
+        let manager = TASK_MANAGER.access();
+        let inner = manager.tasks.access();
+        let tasks: Vec<Arc<Task>> = inner.tasks.iter().chain(inner.cur.iter()).map(Arc::clone).collect();
+        drop(inner);
+        drop(manager);
+        let mut table = DedupTable::new();
+        let merged: usize = tasks.iter().map(|task| {
+            task.task_inner.access().mem.as_mut().map_or(0, |mem| mem.merge_identical(&mut table))
+        }).sum();
+        if merged > 0
+        {
+            println!("dedup: merged {} pages", merged);
+        }
+}
+
+/// kill the task holding the most frames unless that is the current one,
+/// true if a task was killed and the allocation is worth retrying
+fn oom_kill() -> bool
+{
+        let manager = TASK_MANAGER.access();
+        let cur = manager.tasks.access().get_cur_task().unwrap();
+        let victim = match manager.oom_victim()
+        {
+            Some(victim) if victim.pid != cur.pid => victim,
+            _ => return false,
+        };
+        let frames = victim.resident_frames();
+        manager.kill_task(&victim, OOM_EXIT_CODE);
+        let stats = frame_stats();
+        println!("out of memory in pid {}: killed pid {} holding {} frames, {} of {} frames free now",
+            cur.pid.0, victim.pid.0, frames, stats.free, stats.total);
+        true
 }
 
+pub fn exec(app_name: &str, args: Vec<String>, envs: Vec<String>) -> Result<(), ElfError>
+{
+    let cur = TASK_MANAGER.access().tasks.access().get_cur_task().unwrap();
+    let res = loop
+    {
+        match cur.exec(app_name, &args, &envs)
+        {
+            Err(ElfError::OutOfMemory) if swap_out_some() || oom_kill() => continue,
+            res => break res,
+        }
+    };
+    if let Err(err) = res
+    {
+        println!("exec {} in pid {}: {:?}", app_name, get_current_pid(), err);
+    }
+    res
+}
+
+/// what wait returns when pid names no child of the caller
+const WAIT_NO_CHILD : isize = -1;
+/// what wait returns when the child is still running, the caller may try again
+const WAIT_RUNNING : isize = -2;
+
+/// the child is reaped even if its exit code can't be stored at user_addr,
+/// which may be 0 to not store it at all
 pub fn wait(pid: isize, user_addr: usize) -> isize
 {
-    TASK_MANAGER.access().wait(pid, user_addr)
+    let reaped = TASK_MANAGER.access().wait(pid);
+    match reaped
+    {
+        Ok((pid, exit_code)) =>
+        {
+            if user_addr != 0
+            {
+                if let Err(err) = write_user(user_addr, &exit_code)
+                {
+                    return err.errno();
+                }
+            }
+            pid
+        },
+        Err(code) => code,
+    }
+}
+
+pub fn with_current_mem<T>(f: impl FnOnce(&mut MemorySet) -> T) -> T
+{
+        let cur = TASK_MANAGER.access().tasks.access().get_cur_task().unwrap();
+        let mut task_inner = cur.task_inner.access();
+        f(task_inner.mem.as_mut().unwrap())
+}
+
+pub fn get_current_pid() -> isize
+{
+        TASK_MANAGER.access().tasks.access().get_cur_task().unwrap().pid.0
+}
+
+pub fn get_current_name() -> String
+{
+        let cur = TASK_MANAGER.access().tasks.access().get_cur_task().unwrap();
+        let name = cur.task_inner.access().name.clone();
+        name
+}
+
+pub fn is_stack_overflow(va: usize) -> bool
+{
+        with_current_mem(|mem| mem.is_stack_guard(VirAddr::from(va)))
+}
+
+/// resolve a fault of the current task, swapping pages out or running the oom killer when frames run out,
+/// Err(OutOfMemory) once the current task is the one to go
+pub fn handle_page_fault(va: usize, write: bool) -> Result<(), MemError>
+{
+        loop
+        {
+            match with_current_mem(|mem| mem.handle_page_fault(VirAddr::from(va), write))
+            {
+                Err(MemError::OutOfMemory) if swap_out_some() || oom_kill() => continue,
+                res => return res,
+            }
+        }
 }
 
 pub fn get_current_task_ctx() -> usize
@@ -490,6 +679,12 @@ pub fn get_current_task_pgt() -> PhyPage
         }
 }
 
+/// tasks come from a slab cache from now on, the init task is already on the heap
+pub fn init()
+{
+        register_arc::<Task>("task");
+}
+
 pub fn run() -> !
 {
         TASK_MANAGER.access().run_task()
@@ -4,7 +4,7 @@ use crate::sbi::settimer;
 
 const MS_PER_SEC:usize = 1000;
 
-fn get_time() -> usize
+pub fn get_time() -> usize
 {
     unsafe
     {
@@ -1,6 +1,7 @@
 use riscv::register::sstatus::{self, Sstatus, SPP};
 use crate::config::TRAPFRAME;
 use super::trap_handler;
+use crate::mm::flush_on_switch;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Context
@@ -12,6 +13,7 @@ pub struct Context
     pub kernel_stack : usize,
     pub handler : usize,
     pub trapframe : usize, //in user addr
+    pub flush_tlb : usize, //non zero if usertrap has to sfence.vma after switching satp
 }
 
 impl Context
@@ -34,6 +36,7 @@ impl Context
             kernel_stack,
             handler : trap_handler as usize,
             trapframe: TRAPFRAME, 
+            flush_tlb: flush_on_switch() as usize,
         };
         c.set_sp(user_stack);
         c
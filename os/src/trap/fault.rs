@@ -0,0 +1,111 @@
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+use riscv::register::scause::{Scause, Exception, Trap};
+use crate::mm::{MemError, VirAddr};
+use crate::task::{exit, handle_page_fault, with_current_mem, get_current_pid, get_current_name, is_stack_overflow, OOM_EXIT_CODE};
+
+/// a task killed for a fault exits with the negated signal number
+pub const SIGILL : isize = 4;
+pub const SIGTRAP : isize = 5;
+pub const SIGBUS : isize = 7;
+pub const SIGKILL : isize = 9;
+pub const SIGSEGV : isize = 11;
+
+/// scause code of a misaligned load, riscv 0.7 has no Exception for it
+const LOAD_MISALIGNED : usize = 4;
+
+/// every exception raised by user code except ecall ends up here,
+/// page faults the address space can resolve return, anything else kills the task
+pub fn handle_user_fault(scause: Scause, stval: usize, sepc: usize)
+{
+    let write = match scause.cause()
+    {
+        Trap::Exception(Exception::StorePageFault) => Some(true),
+        Trap::Exception(Exception::LoadPageFault) | Trap::Exception(Exception::InstructionPageFault) => Some(false),
+        _ => None,
+    };
+    if let Some(write) = write
+    {
+        match handle_page_fault(stval, write)
+        {
+            Ok(()) => return,
+            Err(MemError::BadAccess) => {},
+            Err(err) => kill_for_memory(err, scause, stval, sepc),
+        }
+    }
+    let (signal, reason) = classify(scause, stval);
+    report(signal, reason, scause, stval, sepc);
+    exit(-signal);
+}
+
+fn classify(scause: Scause, stval: usize) -> (isize, &'static str)
+{
+    match scause.cause()
+    {
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault) if is_stack_overflow(stval) => (SIGSEGV, "stack overflow"),
+        Trap::Exception(Exception::StorePageFault) => (SIGSEGV, "store page fault"),
+        Trap::Exception(Exception::LoadPageFault) => (SIGSEGV, "load page fault"),
+        Trap::Exception(Exception::InstructionPageFault) => (SIGSEGV, "instruction page fault"),
+        Trap::Exception(Exception::StoreFault) => (SIGSEGV, "store access fault"),
+        Trap::Exception(Exception::LoadFault) => (SIGSEGV, "load access fault"),
+        Trap::Exception(Exception::InstructionFault) => (SIGSEGV, "instruction access fault"),
+        Trap::Exception(Exception::StoreMisaligned) => (SIGBUS, "misaligned store"),
+        Trap::Exception(Exception::InstructionMisaligned) => (SIGBUS, "misaligned instruction"),
+        Trap::Exception(Exception::IllegalInstruction) => (SIGILL, "illegal instruction"),
+        Trap::Exception(Exception::Breakpoint) => (SIGTRAP, "breakpoint"),
+        _ if scause.code() == LOAD_MISALIGNED => (SIGBUS, "misaligned load"),
+        _ => (SIGILL, "unknown exception"),
+    }
+}
+
+fn signal_name(signal: isize) -> &'static str
+{
+    match signal
+    {
+        SIGILL => "SIGILL",
+        SIGTRAP => "SIGTRAP",
+        SIGBUS => "SIGBUS",
+        SIGKILL => "SIGKILL",
+        SIGSEGV => "SIGSEGV",
+        _ => "signal",
+    }
+}
+
+/// the area covering va as "[start, end) permit type", or why there is none
+fn describe_vma(va: usize) -> String
+{
+    with_current_mem(|mem| {
+        match mem.area_at(VirAddr::from(va))
+        {
+            Some(area) => format!("[{:#x}, {:#x}) {:?} {:?}",
+                usize::from(VirAddr::from(area.range.current_left())),
+                usize::from(VirAddr::from(area.range.right())),
+                area.permit, area.map_type),
+            None if mem.is_stack_guard(VirAddr::from(va)) => String::from("none, stack guard"),
+            None => String::from("none"),
+        }
+    })
+}
+
+fn report(signal: isize, reason: &str, scause: Scause, stval: usize, sepc: usize)
+{
+    println!("[kernel] pid {} ({}) killed by {}: {}", get_current_pid(), get_current_name(), signal_name(signal), reason);
+    println!("[kernel]     scause {:#x} ({:?}), stval {:#x}, sepc {:#x}", scause.bits(), scause.cause(), stval, sepc);
+    println!("[kernel]     vma {}", describe_vma(stval));
+}
+
+/// the fault was fine but no frame could be found for it
+fn kill_for_memory(err: MemError, scause: Scause, stval: usize, sepc: usize) -> !
+{
+    let (frames, limit) = with_current_mem(|mem| (mem.resident_frames(), mem.frame_limit));
+    let reason = match (err, limit)
+    {
+        (MemError::OverLimit, Some(limit)) => format!("{} frames resident, over its limit of {}", frames, limit),
+        _ => format!("out of memory holding {} frames", frames),
+    };
+    report(SIGKILL, &reason, scause, stval, sepc);
+    exit(OOM_EXIT_CODE)
+}
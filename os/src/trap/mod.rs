@@ -1,4 +1,5 @@
 pub mod context;
+mod fault;
 use riscv::register::
 { 
     scause::
@@ -10,8 +11,9 @@ use riscv::register::
     },
     stval, stvec, sstatus,
 };
-use crate::{syscall::sys_call, mm::{output_virpage_entry, VirPage}};
-use crate::task::{exit, suspend, get_current_task_ctx,get_current_task_satp, get_current_task_pgt};
+use crate::{syscall::sys_call, mm::{VirPage, flush_on_switch}};
+use crate::task::{suspend, dedup_tick, get_current_task_ctx,get_current_task_satp, get_current_task_pgt};
+use fault::handle_user_fault;
 use crate::timer::set_next_timer_intr_in_ms;
 use context::Context;
 use crate::config::{TRAMPOLINE, TRAPFRAME};
@@ -40,7 +42,7 @@ pub fn init()
 pub fn before_trapret()
 { 
     let ctx = get_current_task_ctx();
-    let trapret_func : fn(usize, usize) -> ! =
+    let trapret_func : fn(usize, usize, usize) -> ! =
     unsafe
     {
         core::mem::transmute(
@@ -53,7 +55,7 @@ pub fn before_trapret()
     unsafe
     {
         asm!("fence.i");
-        trapret_func(TRAPFRAME, get_current_task_satp())
+        trapret_func(TRAPFRAME, get_current_task_satp(), flush_on_switch() as usize)
     }
 }
 
@@ -87,22 +89,17 @@ pub fn trap_handler()
         Trap::Exception(Exception::UserEnvCall) =>
         {
             ctx.sepc += 4;
-            ctx.regs[10] = sys_call(ctx.regs[17], [ctx.regs[10], ctx.regs[11], ctx.regs[12]]) as usize;
+            ctx.regs[10] = sys_call(ctx.regs[17], [ctx.regs[10], ctx.regs[11], ctx.regs[12], ctx.regs[13], ctx.regs[14], ctx.regs[15]]) as usize;
         }
-        Trap::Exception(Exception::StoreFault) =>
+        Trap::Exception(_) =>
         {
-            println!("store fault");
-            exit(1);
-        }
-        Trap::Exception(Exception::IllegalInstruction) =>
-        {
-            println!("illegal inst");
-            exit(1);
+            handle_user_fault(scause, val, ctx.sepc);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) =>
         {
             set_next_timer_intr_in_ms(10);
             println!("timer interupt happen");
+            dedup_tick();
             suspend();
         }
         _  =>
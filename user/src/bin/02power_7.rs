@@ -7,7 +7,7 @@ extern crate user_lib;
 const LEN: usize = 100;
 
 #[no_mangle]
-fn main() -> i32 {
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
     let p = 7u64;
     let m = 998244353u64;
     let iter: usize = 160000;
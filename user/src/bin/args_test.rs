@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exec, getenv};
+
+#[no_mangle]
+fn main(argc: usize, argv: &[&str]) -> i32 {
+    println!("argc = {}", argc);
+    for (i, arg) in argv.iter().enumerate() {
+        println!("argv[{}] = {}", i, arg);
+    }
+    assert_eq!(argc, argv.len());
+
+    if argc == 1 {
+        // started by the kernel: the app name is the only argument and there is no environment
+        assert_eq!(argv[0], "args_test");
+        assert_eq!(getenv("GREETING"), None);
+        exec("args_test", &["args_test", "hello", "two words", ""], &["GREETING=hi", "EMPTY="]);
+        panic!("exec returned");
+    }
+
+    assert_eq!(argv, ["args_test", "hello", "two words", ""]);
+    assert_eq!(getenv("GREETING"), Some("hi"));
+    assert_eq!(getenv("EMPTY"), Some(""));
+    assert_eq!(getenv("GREET"), None);
+    // an app that does not exist leaves this one running
+    assert!(exec("no_such_app", &[], &[]) < 0);
+    println!("args_test passed!");
+    0
+}
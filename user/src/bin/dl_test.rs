@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{getenv, vmas, PROT_EXEC, VMA_SHARED};
+
+/// the kernel maps the interpreter of a program past INTERP_BASE, below the mmap range
+const INTERP_BASE: usize = 0x8_0000_0000;
+const MMAP_BASE: usize = 0x10_0000_0000;
+
+fn in_interp(addr: usize) -> bool {
+    INTERP_BASE <= addr && addr < MMAP_BASE
+}
+
+#[no_mangle]
+fn main(argc: usize, argv: &[&str]) -> i32 {
+    // the kernel starts the copy linked against libuser.so as dl_test_dyn
+    let dynamic = argc > 0 && argv[0].ends_with("_dyn");
+    println!("{} started, dynamic = {}", argv.get(0).unwrap_or(&"?"), dynamic);
+
+    // with the loader, user_lib is libuser.so mapped above the program
+    let lib_fn = getenv as *const () as usize;
+    let own_fn = main as *const () as usize;
+    assert!(!in_interp(own_fn));
+    assert_eq!(in_interp(lib_fn), dynamic);
+    assert_eq!(vmas().iter().any(|vma| in_interp(vma.start)), dynamic);
+    if dynamic {
+        // the text of libuser.so comes from frames every dynamic app shares
+        let lib_text = vmas().into_iter().find(|vma| vma.start <= lib_fn && lib_fn < vma.end).unwrap();
+        assert_eq!(lib_text.kind, VMA_SHARED);
+        assert!(lib_text.prot & PROT_EXEC != 0);
+    }
+
+    // statics and the heap of user_lib only work once the loader relocated them
+    let squares: Vec<usize> = (0..64).map(|i| i * i).collect();
+    assert_eq!(squares.iter().sum::<usize>(), 85344);
+    assert_eq!(getenv("DL_TEST"), None);
+
+    println!("dl_test passed!");
+    0
+}
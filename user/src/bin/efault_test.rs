@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{write, yield_, mmap, mprotect, fork, exit, sys_getrlimit, sys_waitpid, RLIMIT_RSS, PROT_READ, PROT_WRITE, MAP_PRIVATE, MAP_ANONYMOUS};
+
+const EFAULT: isize = 14;
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    // nothing is mapped at the bottom of the address space
+    let unmapped = unsafe { core::slice::from_raw_parts(0x1000 as *const u8, 16) };
+    assert_eq!(write(1, unmapped), -EFAULT);
+
+    // the kernel must check W before storing for us
+    let page = mmap(0, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS) as usize;
+    assert_eq!(mprotect(page, PAGE_SIZE, PROT_READ), 0);
+    let rlim = unsafe { &mut *(page as *mut [usize; 2]) };
+    assert_eq!(sys_getrlimit(RLIMIT_RSS, rlim), -EFAULT);
+
+    // the child is still reaped when its exit code can't be stored
+    let pid = fork();
+    if pid == 0 {
+        exit(3);
+    }
+    let bad_code = unsafe { &mut *(page as *mut isize) };
+    loop {
+        match sys_waitpid(pid, bad_code) {
+            -1 => {
+                yield_();
+            }
+            ret => {
+                assert_eq!(ret, -EFAULT);
+                break;
+            }
+        }
+    }
+    assert_eq!(sys_waitpid(pid, bad_code), -1);
+    println!("efault_test passed!");
+    0
+}
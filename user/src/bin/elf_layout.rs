@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+
+// the linker packs .rodata, .data and .bss back to back, so their first and last
+// pages are shared with the neighbouring sections
+static TABLE: [u32; 3 * PAGE_SIZE / 4] = {
+    let mut table = [0u32; 3 * PAGE_SIZE / 4];
+    let mut i = 0;
+    while i < table.len() {
+        table[i] = (i as u32).wrapping_mul(2654435761);
+        i += 1;
+    }
+    table
+};
+static mut DATA: [u8; PAGE_SIZE + 123] = [0x5a; PAGE_SIZE + 123];
+static mut BSS: [u64; 5 * PAGE_SIZE / 8 + 7] = [0; 5 * PAGE_SIZE / 8 + 7];
+
+#[inline(never)]
+fn checksum(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0usize, |acc, b| acc.wrapping_mul(31).wrapping_add(*b as usize))
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    // initialized read-only bytes sit at their exact addresses across pages
+    for (i, value) in TABLE.iter().enumerate() {
+        assert_eq!(*value, (i as u32).wrapping_mul(2654435761));
+    }
+    println!("rodata {:#x}..{:#x} ok", TABLE.as_ptr() as usize, TABLE.as_ptr() as usize + core::mem::size_of_val(&TABLE));
+
+    // initialized writable bytes, also past the end of the file bytes of the page before
+    unsafe {
+        let data = &mut *core::ptr::addr_of_mut!(DATA);
+        assert!(data.iter().all(|b| *b == 0x5a));
+        data[0] = 1;
+        data[data.len() - 1] = 2;
+        assert_eq!(checksum(&data[1..data.len() - 1]), checksum(&[0x5a; PAGE_SIZE + 121]));
+        println!("data {:#x}..{:#x} ok", data.as_ptr() as usize, data.as_ptr() as usize + data.len());
+    }
+
+    // memory past the file bytes reads zero, including the tail of the last data page
+    unsafe {
+        let bss = &mut *core::ptr::addr_of_mut!(BSS);
+        assert!(bss.iter().all(|v| *v == 0));
+        for (i, v) in bss.iter_mut().enumerate() {
+            *v = i as u64;
+        }
+        assert!(bss.iter().enumerate().all(|(i, v)| *v == i as u64));
+        println!("bss {:#x}..{:#x} ok", bss.as_ptr() as usize, bss.as_ptr() as usize + core::mem::size_of_val(bss));
+    }
+
+    // code in a page it may share with rodata still runs
+    let f: fn(&[u8]) -> usize = checksum;
+    assert_eq!(f(b"elf"), checksum(b"elf"));
+    println!("elf_layout passed!");
+    0
+}
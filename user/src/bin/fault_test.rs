@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::arch::asm;
+use user_lib::{fork, exit, waitpid};
+
+const SIGILL: isize = 4;
+const SIGTRAP: isize = 5;
+const SIGSEGV: isize = 11;
+
+fn load_unmapped() {
+    unsafe {
+        (0x1000 as *const u8).read_volatile();
+    }
+}
+
+fn store_to_text() {
+    unsafe {
+        (main as *const () as *mut u8).write_volatile(0);
+    }
+}
+
+fn jump_unmapped() {
+    let f: fn() = unsafe { core::mem::transmute(0x2000usize) };
+    f();
+}
+
+fn breakpoint() {
+    unsafe {
+        asm!("ebreak");
+    }
+}
+
+fn illegal() {
+    unsafe {
+        asm!("unimp");
+    }
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    let cases: [(&str, fn(), isize); 5] = [
+        ("load from unmapped", load_unmapped, SIGSEGV),
+        ("store to text", store_to_text, SIGSEGV),
+        ("jump to unmapped", jump_unmapped, SIGSEGV),
+        ("breakpoint", breakpoint, SIGTRAP),
+        ("illegal instruction", illegal, SIGILL),
+    ];
+    for (name, case, signal) in cases.iter() {
+        let pid = fork();
+        if pid == 0 {
+            case();
+            exit(0);
+        }
+        let mut code = 0;
+        assert_eq!(waitpid(pid, &mut code), pid);
+        println!("{}: child exited with {}", name, code);
+        assert_eq!(code, -signal);
+    }
+    // every child is reaped, so waiting again finds nobody instead of spinning
+    let mut code = 0;
+    assert_eq!(waitpid(-1, &mut code), -1);
+    println!("fault_test passed!");
+    0
+}
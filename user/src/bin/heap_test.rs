@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{brk, mmap, PROT_READ, PROT_WRITE, MAP_PRIVATE, MAP_ANONYMOUS, MAP_FIXED};
+
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    let boxed = Box::new(42usize);
+    assert_eq!(*boxed, 42);
+
+    let mut v: Vec<usize> = Vec::new();
+    for i in 0..10000 {
+        v.push(i);
+    }
+    assert_eq!(v.iter().sum::<usize>(), 10000 * 9999 / 2);
+
+    let mut s = String::new();
+    for _ in 0..100 {
+        s.push_str("heap");
+    }
+    assert_eq!(s.len(), 400);
+
+    // bigger than one growth step of the heap
+    let big = alloc::vec![7u8; 256 * 1024];
+    assert!(big.iter().all(|b| *b == 7));
+
+    // a fixed mapping can't take the place of the heap under its allocations
+    let last_page = (brk(0) as usize - 1) & !(PAGE_SIZE - 1);
+    assert!(mmap(last_page, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED) < 0);
+    assert!(big.iter().all(|b| *b == 7));
+
+    println!("Test heap OK!");
+    0
+}
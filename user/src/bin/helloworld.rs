@@ -5,7 +5,7 @@
 extern crate user_lib;
 
 #[no_mangle]
-fn main() -> i32
+fn main(_argc: usize, _argv: &[&str]) -> i32
 {
     println!("hello world");
     0
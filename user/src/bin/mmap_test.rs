@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, PROT_READ, PROT_WRITE, MAP_PRIVATE, MAP_ANONYMOUS, MAP_FIXED};
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 4;
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    let start = mmap(0, PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS);
+    assert!(start > 0, "mmap failed");
+    let start = start as usize;
+
+    for i in 0..PAGES {
+        let ptr = (start + i * PAGE_SIZE) as *mut usize;
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(i + 1);
+        }
+    }
+
+    // punch a hole in the middle, the pages around it must survive
+    assert_eq!(munmap(start + PAGE_SIZE, PAGE_SIZE), 0);
+    unsafe {
+        assert_eq!(((start) as *const usize).read_volatile(), 1);
+        assert_eq!(((start + 2 * PAGE_SIZE) as *const usize).read_volatile(), 3);
+    }
+
+    // map the hole again at a fixed address, it comes back zeroed
+    let again = mmap(start + PAGE_SIZE, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED);
+    assert_eq!(again as usize, start + PAGE_SIZE);
+    unsafe {
+        assert_eq!(((start + PAGE_SIZE) as *const usize).read_volatile(), 0);
+    }
+
+    assert_eq!(munmap(start, PAGES * PAGE_SIZE), 0);
+    println!("Test mmap OK!");
+    0
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mprotect, fork, waitpid, exit, PROT_READ, PROT_WRITE, PROT_EXEC, MAP_PRIVATE, MAP_ANONYMOUS};
+
+const PAGE_SIZE: usize = 4096;
+
+/// li a0, 42; ret
+const RETURN_42: [u32; 2] = [0x02a0_0513, 0x0000_8067];
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    let start = mmap(0, 3 * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS);
+    assert!(start > 0, "mmap failed");
+    let start = start as usize;
+    let middle = (start + PAGE_SIZE) as *mut usize;
+    unsafe {
+        middle.write_volatile(7);
+    }
+
+    // only the middle page becomes read-only, the area gets split around it
+    assert_eq!(mprotect(start + PAGE_SIZE, PAGE_SIZE, PROT_READ), 0);
+    unsafe {
+        assert_eq!(middle.read_volatile(), 7);
+        (start as *mut usize).write_volatile(1);
+        ((start + 2 * PAGE_SIZE) as *mut usize).write_volatile(3);
+    }
+    let pid = fork();
+    if pid == 0 {
+        unsafe {
+            middle.write_volatile(8);
+        }
+        exit(0);
+    }
+    let mut code = 0;
+    waitpid(pid, &mut code);
+    assert!(code != 0, "store to a read-only page went through");
+
+    // back to writable, the three pieces merge again
+    assert_eq!(mprotect(start, 3 * PAGE_SIZE, PROT_READ | PROT_WRITE), 0);
+    unsafe {
+        middle.write_volatile(9);
+        assert_eq!(middle.read_volatile(), 9);
+    }
+
+    // jit: write code, then flip the page to read+exec
+    let code_page = start as *mut u32;
+    unsafe {
+        code_page.write_volatile(RETURN_42[0]);
+        code_page.add(1).write_volatile(RETURN_42[1]);
+    }
+    assert_eq!(mprotect(start, PAGE_SIZE, PROT_READ | PROT_EXEC), 0);
+    let f: fn() -> usize = unsafe { core::mem::transmute(code_page) };
+    assert_eq!(f(), 42);
+
+    assert!(mprotect(start + 16 * PAGE_SIZE, PAGE_SIZE, PROT_READ) < 0);
+    println!("mprotect_test passed!");
+    0
+}
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+// statics holding addresses only come out right once the kernel relocated them
+static NAMES: [&str; 3] = ["double", "square", "negate"];
+static OPS: [fn(isize) -> isize; 3] = [double, square, negate];
+static ANSWER: &usize = &42;
+
+fn double(x: isize) -> isize {
+    x * 2
+}
+
+fn square(x: isize) -> isize {
+    x * x
+}
+
+fn negate(x: isize) -> isize {
+    -x
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    let base = main as *const () as usize;
+    println!("main loaded at {:#x}", base);
+    assert!(base >= 0x10000, "the image was not moved off page 0");
+
+    let expected = [14, 49, -7];
+    for i in 0..OPS.len() {
+        let f = OPS[i];
+        assert_eq!(f(7), expected[i]);
+        println!("{}(7) = {}", NAMES[i], f(7));
+    }
+    assert_eq!(*ANSWER, 42);
+    println!("pie_test passed!");
+    0
+}
@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, vmas, page_walk, Vma, PROT_READ, PROT_WRITE, PROT_EXEC, MAP_PRIVATE, MAP_ANONYMOUS, VMA_LAZY};
+
+const PAGE_SIZE: usize = 4096;
+const PTE_V: usize = 1 << 0;
+const PTE_W: usize = 1 << 2;
+
+fn print_map() {
+    println!("{:>16} {:>16} prot kind resident swapped", "start", "end");
+    for vma in vmas() {
+        let prot = [(PROT_READ, 'r'), (PROT_WRITE, 'w'), (PROT_EXEC, 'x')];
+        let mut flags = ['-'; 3];
+        for (i, (bit, c)) in prot.iter().enumerate() {
+            if vma.prot & bit != 0 {
+                flags[i] = *c;
+            }
+        }
+        let kind = ["ident", "framed", "lazy", "shared"].get(vma.kind).unwrap_or(&"?");
+        println!("{:>16x} {:>16x} {}{}{} {:>6} {:>8} {:>7}",
+            vma.start, vma.end, flags[0], flags[1], flags[2], kind, vma.resident, vma.swapped);
+    }
+}
+
+fn area_at(addr: usize) -> Option<Vma> {
+    vmas().into_iter().find(|vma| vma.start <= addr && addr < vma.end)
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    print_map();
+
+    let pages = 8;
+    let addr = mmap(0, pages * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS) as usize;
+    let vma = area_at(addr).expect("the mmap area is not listed");
+    assert_eq!((vma.start, vma.end), (addr, addr + pages * PAGE_SIZE));
+    assert_eq!(vma.prot, PROT_READ | PROT_WRITE);
+    assert_eq!(vma.kind, VMA_LAZY);
+    assert_eq!(vma.resident, 0);
+
+    // two stores and a load, the load maps the zero page which counts as resident too
+    unsafe {
+        (addr as *mut u8).write_volatile(1);
+        ((addr + 2 * PAGE_SIZE) as *mut u8).write_volatile(2);
+        ((addr + 4 * PAGE_SIZE) as *const u8).read_volatile();
+    }
+    assert_eq!(area_at(addr).unwrap().resident, 3);
+
+    let written = page_walk(addr + 2 * PAGE_SIZE + 8);
+    assert!(written.levels > 0 && written.pa != 0);
+    assert_eq!(written.pa % PAGE_SIZE, 8);
+    let leaf = written.ptes[written.levels - 1];
+    assert!(leaf & PTE_V != 0 && leaf & PTE_W != 0);
+
+    let read = page_walk(addr + 4 * PAGE_SIZE);
+    assert!(read.pa != 0);
+    assert_eq!(read.ptes[read.levels - 1] & PTE_W, 0);
+
+    let untouched = page_walk(addr + PAGE_SIZE);
+    assert_eq!(untouched.pa, 0);
+
+    print_map();
+    munmap(addr, pages * PAGE_SIZE);
+    assert!(area_at(addr).is_none());
+    println!("pmap passed!");
+    0
+}
@@ -10,7 +10,7 @@ const STEP: usize = 100000;
 const MOD: u32 = 10007;
 
 #[no_mangle]
-fn main() -> i32 {
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
     let mut pow = [0u32; SIZE];
     let mut index: usize = 0;
     pow[index] = 1;
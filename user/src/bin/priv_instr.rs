@@ -6,7 +6,7 @@ extern crate user_lib;
 use core::arch::asm;
 
 #[no_mangle]
-fn main() -> i32
+fn main(_argc: usize, _argv: &[&str]) -> i32
 {
     println!("use privilage instr");
     unsafe
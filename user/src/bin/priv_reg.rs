@@ -7,7 +7,7 @@ use riscv::register::sstatus::{self, SPP};
 extern crate user_lib;
 
 #[no_mangle]
-fn main() -> i32
+fn main(_argc: usize, _argv: &[&str]) -> i32
 {
     println!("try to write a priv reg");
     unsafe
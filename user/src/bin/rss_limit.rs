@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, getrlimit, setrlimit, RLIMIT_RSS, RLIM_INFINITY, PROT_READ, PROT_WRITE, MAP_PRIVATE, MAP_ANONYMOUS};
+
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    let limit = getrlimit(RLIMIT_RSS).unwrap();
+    assert_eq!(limit[0], RLIM_INFINITY);
+
+    // 64 pages is plenty for code, stack and a few heap pages
+    assert_eq!(setrlimit(RLIMIT_RSS, 64 * PAGE_SIZE, RLIM_INFINITY), 0);
+    assert_eq!(getrlimit(RLIMIT_RSS).unwrap()[0], 64 * PAGE_SIZE);
+    assert_eq!(setrlimit(RLIMIT_RSS, 2 * PAGE_SIZE, PAGE_SIZE), -1);
+
+    let pages = 128;
+    let addr = mmap(0, pages * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS);
+    assert!(addr > 0);
+    println!("touching {} pages with a limit of 64, expect to be killed", pages);
+    for i in 0..pages {
+        unsafe {
+            ((addr as usize + i * PAGE_SIZE) as *mut u8).write_volatile(i as u8);
+        }
+    }
+    println!("should not reach here");
+    0
+}
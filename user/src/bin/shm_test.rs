@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, waitpid, exit, yield_, shmget, shmat, shmdt, shmctl, mprotect, IPC_CREAT, IPC_RMID, SHM_RDONLY, PROT_READ, PROT_WRITE};
+
+const KEY: usize = 0x5348;
+const SLOTS: usize = 64;
+
+/// a one-producer one-consumer ring living in the shared segment
+#[repr(C)]
+struct Channel {
+    head: usize,
+    tail: usize,
+    buf: [usize; SLOTS],
+}
+
+fn attach() -> &'static mut Channel {
+    let id = shmget(KEY, core::mem::size_of::<Channel>(), IPC_CREAT);
+    assert!(id >= 0, "shmget failed");
+    let addr = shmat(id as usize, 0, 0);
+    assert!(addr > 0, "shmat failed");
+    unsafe { &mut *(addr as *mut Channel) }
+}
+
+fn produce(chan: &mut Channel, count: usize) {
+    for i in 0..count {
+        while unsafe { core::ptr::read_volatile(&chan.head) } - unsafe { core::ptr::read_volatile(&chan.tail) } == SLOTS {
+            yield_();
+        }
+        chan.buf[chan.head % SLOTS] = i;
+        unsafe { core::ptr::write_volatile(&mut chan.head, chan.head + 1) };
+    }
+}
+
+fn consume(chan: &mut Channel, count: usize) -> usize {
+    let mut sum = 0;
+    for _ in 0..count {
+        while unsafe { core::ptr::read_volatile(&chan.head) } == unsafe { core::ptr::read_volatile(&chan.tail) } {
+            yield_();
+        }
+        sum += chan.buf[chan.tail % SLOTS];
+        unsafe { core::ptr::write_volatile(&mut chan.tail, chan.tail + 1) };
+    }
+    sum
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    let count = 1000;
+    let chan = attach();
+    let pid = fork();
+    if pid == 0 {
+        // the child attaches by key on its own as well, both mappings see the same frames
+        let own = attach();
+        produce(own, count);
+        exit(0);
+    }
+    let sum = consume(chan, count);
+    assert_eq!(sum, count * (count - 1) / 2);
+    let mut code = 0;
+    waitpid(pid, &mut code);
+
+    let id = shmget(KEY, 0, 0);
+    // a read-only attachment can't be made writable behind the segment's back
+    let ro = shmat(id as usize, 0, SHM_RDONLY);
+    assert!(ro > 0);
+    assert!(mprotect(ro as usize, 4096, PROT_READ | PROT_WRITE) < 0);
+    assert_eq!(mprotect(ro as usize, 4096, PROT_READ), 0);
+    assert_eq!(shmdt(ro as usize), 0);
+    assert_eq!(shmdt(chan as *mut Channel as usize), 0);
+    assert_eq!(shmctl(id as usize, IPC_RMID), 0);
+    assert!(shmget(KEY, 0, 0) < 0);
+    println!("shm_test passed!");
+    0
+}
@@ -0,0 +1,23 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+fn recurse(depth: usize, limit: usize) -> usize {
+    let frame = [depth as u8; 1024];
+    if depth == limit {
+        return frame[0] as usize;
+    }
+    recurse(depth + 1, limit) + unsafe { core::ptr::read_volatile(&frame[1]) } as usize
+}
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    // ~200 KiB of stack, far more than the pages mapped at exec
+    recurse(0, 200);
+    println!("deep recursion OK, now overflowing the stack");
+    recurse(0, usize::MAX);
+    println!("should not reach here");
+    0
+}
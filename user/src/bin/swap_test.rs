@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, PROT_READ, PROT_WRITE, MAP_PRIVATE, MAP_ANONYMOUS};
+
+const PAGE_SIZE: usize = 4096;
+// 10 MiB, more than the whole of physical memory
+const PAGES: usize = 2560;
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    let start = mmap(0, PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS);
+    assert!(start > 0, "mmap failed");
+    let start = start as usize;
+
+    for i in 0..PAGES {
+        unsafe {
+            ((start + i * PAGE_SIZE) as *mut usize).write_volatile(i * 7 + 1);
+        }
+    }
+    println!("wrote {} pages, reading them back through swap", PAGES);
+    for i in 0..PAGES {
+        unsafe {
+            assert_eq!(((start + i * PAGE_SIZE) as *const usize).read_volatile(), i * 7 + 1);
+        }
+    }
+
+    assert_eq!(munmap(start, PAGES * PAGE_SIZE), 0);
+    println!("swap_test passed!");
+    0
+}
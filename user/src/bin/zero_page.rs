@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, setrlimit, RLIMIT_RSS, RLIM_INFINITY, PROT_READ, PROT_WRITE, MAP_PRIVATE, MAP_ANONYMOUS};
+
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+fn main(_argc: usize, _argv: &[&str]) -> i32 {
+    assert_eq!(setrlimit(RLIMIT_RSS, 64 * PAGE_SIZE, RLIM_INFINITY), 0);
+
+    // reading untouched pages maps the zero page, which counts against no limit
+    let pages = 256;
+    let addr = mmap(0, pages * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS) as usize;
+    for i in 0..pages {
+        let value = unsafe { ((addr + i * PAGE_SIZE) as *const u8).read_volatile() };
+        assert_eq!(value, 0);
+    }
+    println!("read {} zero pages under a limit of 64", pages);
+
+    // a store gives the page a frame of its own, the others still read zero
+    for i in 0..8 {
+        unsafe {
+            ((addr + i * 2 * PAGE_SIZE) as *mut u8).write_volatile(i as u8 + 1);
+        }
+    }
+    for i in 0..16 {
+        let value = unsafe { ((addr + i * PAGE_SIZE) as *const u8).read_volatile() };
+        let expected = if i % 2 == 0 { i as u8 / 2 + 1 } else { 0 };
+        assert_eq!(value, expected);
+    }
+    println!("zero_page passed!");
+    0
+}
@@ -0,0 +1,342 @@
+//! the dynamic loader, built into libuser.so: the kernel maps libuser.so next to a program
+//! whose PT_INTERP names it and starts here, the loader relocates both against each other
+//! and calls main of the program. Until that is done no pointer in a static is right, so
+//! nothing here may touch statics, format or panic.
+
+use core::arch::global_asm;
+use crate::syscall::{sys_write, sys_exit};
+
+const AT_NULL : usize = 0;
+const AT_PHDR : usize = 3;
+const AT_PHNUM : usize = 5;
+const AT_BASE : usize = 7;
+
+const PT_DYNAMIC : u32 = 2;
+const PT_PHDR : u32 = 6;
+
+const DT_NULL : usize = 0;
+const DT_PLTRELSZ : usize = 2;
+const DT_HASH : usize = 4;
+const DT_STRTAB : usize = 5;
+const DT_SYMTAB : usize = 6;
+const DT_RELA : usize = 7;
+const DT_RELASZ : usize = 8;
+const DT_JMPREL : usize = 23;
+
+const SHN_UNDEF : u16 = 0;
+const SHN_ABS : u16 = 0xfff1;
+const STB_LOCAL : u8 = 0;
+const STB_WEAK : u8 = 2;
+
+const R_RISCV_NONE : u32 = 0;
+const R_RISCV_64 : u32 = 2;
+const R_RISCV_RELATIVE : u32 = 3;
+const R_RISCV_COPY : u32 = 4;
+const R_RISCV_JUMP_SLOT : u32 = 5;
+
+/// offsets of e_phoff and e_phnum in the elf header
+const EHDR_PHOFF : usize = 32;
+const EHDR_PHNUM : usize = 56;
+
+#[repr(C)]
+struct Phdr
+{
+    p_type: u32,
+    p_flags: u32,
+    p_offset: usize,
+    p_vaddr: usize,
+    p_paddr: usize,
+    p_filesz: usize,
+    p_memsz: usize,
+    p_align: usize,
+}
+
+#[repr(C)]
+struct Sym
+{
+    st_name: u32,
+    st_info: u8,
+    st_other: u8,
+    st_shndx: u16,
+    st_value: usize,
+    st_size: usize,
+}
+
+#[repr(C)]
+struct Rela
+{
+    r_offset: usize,
+    r_info: usize,
+    r_addend: usize,
+}
+
+/// a mapped object as its dynamic segment describes it, addresses already moved by bias
+#[derive(Clone, Copy)]
+struct Object
+{
+    bias: usize,
+    symtab: *const Sym,
+    strtab: *const u8,
+    /// symbols in symtab, the nchain of the DT_HASH table
+    nsyms: usize,
+    /// (address, size) of the DT_RELA and DT_JMPREL tables
+    rela: (usize, usize),
+    jmprel: (usize, usize),
+}
+
+global_asm!(
+    ".section .text",
+    ".globl _dl_start",
+    "_dl_start:",
+    "    mv a0, sp",
+    "    call dl_main",
+);
+
+unsafe fn cstr_eq(mut a: *const u8, mut b: *const u8) -> bool
+{
+    loop
+    {
+        if *a != *b
+        {
+            return false;
+        }
+        if *a == 0
+        {
+            return true;
+        }
+        a = a.add(1);
+        b = b.add(1);
+    }
+}
+
+unsafe fn cstr_len(s: *const u8) -> usize
+{
+    let mut len = 0;
+    while *s.add(len) != 0
+    {
+        len += 1;
+    }
+    len
+}
+
+/// write what went wrong and name to stdout and exit, the message is a literal the code
+/// finds relative to pc so it is fine to use before relocation
+unsafe fn fail(msg: &str, name: *const u8) -> !
+{
+    sys_write(1, msg.as_bytes());
+    if !name.is_null()
+    {
+        sys_write(1, core::slice::from_raw_parts(name, cstr_len(name)));
+    }
+    sys_write(1, b"\n");
+    sys_exit(-1)
+}
+
+impl Object
+{
+    /// read the dynamic segment among the phnum program headers at phdrs
+    unsafe fn new(bias: usize, phdrs: *const Phdr, phnum: usize) -> Option<Self>
+    {
+        let mut dynamic = 0;
+        for i in 0..phnum
+        {
+            let ph = &*phdrs.add(i);
+            if ph.p_type == PT_DYNAMIC
+            {
+                dynamic = bias + ph.p_vaddr;
+            }
+        }
+        if dynamic == 0
+        {
+            return None;
+        }
+
+        let mut obj = Object
+        {
+            bias,
+            symtab: core::ptr::null(),
+            strtab: core::ptr::null(),
+            nsyms: 0,
+            rela: (0, 0),
+            jmprel: (0, 0),
+        };
+        let mut entry = dynamic as *const [usize; 2];
+        while (*entry)[0] != DT_NULL
+        {
+            let value = (*entry)[1];
+            let tag = (*entry)[0];
+            if tag == DT_SYMTAB
+            {
+                obj.symtab = (bias + value) as *const Sym;
+            }
+            else if tag == DT_STRTAB
+            {
+                obj.strtab = (bias + value) as *const u8;
+            }
+            else if tag == DT_HASH
+            {
+                obj.nsyms = *((bias + value) as *const u32).add(1) as usize;
+            }
+            else if tag == DT_RELA
+            {
+                obj.rela.0 = bias + value;
+            }
+            else if tag == DT_RELASZ
+            {
+                obj.rela.1 = value;
+            }
+            else if tag == DT_JMPREL
+            {
+                obj.jmprel.0 = bias + value;
+            }
+            else if tag == DT_PLTRELSZ
+            {
+                obj.jmprel.1 = value;
+            }
+            entry = entry.add(1);
+        }
+        Some(obj)
+    }
+
+    /// the address of the symbol name defined in this object
+    unsafe fn find(&self, name: *const u8) -> Option<usize>
+    {
+        for i in 1..self.nsyms
+        {
+            let sym = &*self.symtab.add(i);
+            if sym.st_shndx == SHN_UNDEF || sym.st_info >> 4 == STB_LOCAL
+            {
+                continue;
+            }
+            if cstr_eq(self.strtab.add(sym.st_name as usize), name)
+            {
+                return Some(if sym.st_shndx == SHN_ABS { sym.st_value } else { self.bias + sym.st_value });
+            }
+        }
+        None
+    }
+
+    /// apply both relocation tables, symbols are looked up in objects in order
+    unsafe fn relocate(&self, objects: &[Object])
+    {
+        for (addr, size) in [self.rela, self.jmprel]
+        {
+            for i in 0..size / core::mem::size_of::<Rela>()
+            {
+                let rela = &*(addr as *const Rela).add(i);
+                let target = (self.bias + rela.r_offset) as *mut usize;
+                let kind = rela.r_info as u32;
+                if kind == R_RISCV_NONE
+                {
+                    continue;
+                }
+                if kind == R_RISCV_RELATIVE
+                {
+                    *target = self.bias + rela.r_addend;
+                    continue;
+                }
+
+                let sym = &*self.symtab.add(rela.r_info >> 32);
+                let name = self.strtab.add(sym.st_name as usize);
+                // a copy relocation takes the bytes from wherever else the symbol lives
+                let skip = if kind == R_RISCV_COPY { 1 } else { 0 };
+                let found = objects[skip..].iter().find_map(|obj| obj.find(name));
+                let value = match found
+                {
+                    Some(value) => value,
+                    None if sym.st_info >> 4 == STB_WEAK => 0,
+                    None => fail("dl: undefined symbol ", name),
+                };
+                if kind == R_RISCV_64
+                {
+                    *target = value.wrapping_add(rela.r_addend);
+                }
+                else if kind == R_RISCV_JUMP_SLOT
+                {
+                    *target = value;
+                }
+                else if kind == R_RISCV_COPY
+                {
+                    core::ptr::copy_nonoverlapping(value as *const u8, target as *mut u8, sym.st_size);
+                }
+                else
+                {
+                    fail("dl: unsupported relocation against ", name);
+                }
+            }
+        }
+    }
+}
+
+/// sp points at argc, then argv, envp and the auxiliary vector the kernel left
+#[no_mangle]
+unsafe extern "C" fn dl_main(sp: *const usize) -> !
+{
+    let argc = *sp;
+    let mut cur = sp.add(argc + 2);
+    while *cur != 0
+    {
+        cur = cur.add(1);
+    }
+    cur = cur.add(1);
+    let (mut base, mut phdr, mut phnum) = (0, 0, 0);
+    while *cur != AT_NULL
+    {
+        let value = *cur.add(1);
+        if *cur == AT_BASE
+        {
+            base = value;
+        }
+        else if *cur == AT_PHDR
+        {
+            phdr = value;
+        }
+        else if *cur == AT_PHNUM
+        {
+            phnum = value;
+        }
+        cur = cur.add(2);
+    }
+    if base == 0 || phdr == 0
+    {
+        fail("dl: not started as an interpreter", core::ptr::null());
+    }
+
+    // libuser.so is linked with its headers in the first segment
+    let own_phdrs = (base + *((base + EHDR_PHOFF) as *const usize)) as *const Phdr;
+    let own_phnum = *((base + EHDR_PHNUM) as *const u16) as usize;
+    let lib = match Object::new(base, own_phdrs, own_phnum)
+    {
+        Some(obj) => obj,
+        None => fail("dl: libuser.so has no dynamic segment", core::ptr::null()),
+    };
+
+    // the program says where its headers were linked, which gives its bias
+    let phdrs = phdr as *const Phdr;
+    let mut bias = None;
+    for i in 0..phnum
+    {
+        let ph = &*phdrs.add(i);
+        if ph.p_type == PT_PHDR
+        {
+            bias = Some(phdr - ph.p_vaddr);
+        }
+    }
+    let program = match bias.and_then(|bias| Object::new(bias, phdrs, phnum))
+    {
+        Some(obj) => obj,
+        None => fail("dl: the program has no PT_PHDR or no dynamic segment", core::ptr::null()),
+    };
+
+    // the program comes first so its definitions win, as for main
+    let objects = [program, lib];
+    lib.relocate(&objects);
+    program.relocate(&objects);
+
+    let main = match program.find(b"main\0".as_ptr())
+    {
+        Some(main) => core::mem::transmute::<usize, crate::Main>(main),
+        None => fail("dl: the program has no main", core::ptr::null()),
+    };
+    crate::exit(crate::run_main(sp, main))
+}
@@ -0,0 +1,46 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use buddy_system_allocator::LockedHeap;
+use crate::sbrk;
+
+/// least amount the break is moved by when the heap runs dry
+const HEAP_GROW : usize = 16 * 4096;
+
+struct BrkHeap(LockedHeap);
+
+#[global_allocator]
+static HEAP : BrkHeap = BrkHeap(LockedHeap::new());
+
+unsafe impl GlobalAlloc for BrkHeap
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8
+    {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout)
+        {
+            return ptr.as_ptr();
+        }
+
+        // a block of twice the request always holds an aligned buddy big enough
+        let size = layout.size().max(layout.align()).next_power_of_two();
+        let grow = (size * 2).max(HEAP_GROW);
+        let start = sbrk(grow as isize);
+        if start == -1
+        {
+            return core::ptr::null_mut();
+        }
+        heap.add_to_heap(start as usize, start as usize + grow);
+        heap.alloc(layout).map_or(core::ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+    {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
+
+#[alloc_error_handler]
+fn handle_alloc_error(layout: Layout) -> !
+{
+    panic!("user heap allocation error, layout = {:?}", layout);
+}
@@ -2,6 +2,7 @@
 #![feature(linkage)]
 #![feature(panic_info_message)]
 #![feature(asm)]
+#![feature(alloc_error_handler)]
 #![no_main]
 
 #[macro_use]
@@ -9,9 +10,64 @@ pub mod console;
 
 mod syscall;
 mod lang_items;
+mod heap;
+mod dl;
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::global_asm;
 
 pub use syscall::*;
 
+pub const PROT_READ : usize = 1;
+pub const PROT_WRITE : usize = 2;
+pub const PROT_EXEC : usize = 4;
+
+pub const MAP_PRIVATE : usize = 0x02;
+pub const MAP_FIXED : usize = 0x10;
+pub const MAP_ANONYMOUS : usize = 0x20;
+
+pub const IPC_PRIVATE : usize = 0;
+pub const IPC_CREAT : usize = 0o1000;
+pub const IPC_EXCL : usize = 0o2000;
+pub const IPC_RMID : usize = 0;
+pub const SHM_RDONLY : usize = 0o10000;
+
+pub const RLIMIT_RSS : usize = 5;
+pub const RLIM_INFINITY : usize = usize::MAX;
+
+pub const VMA_IDENTICAL : usize = 0;
+pub const VMA_FRAMED : usize = 1;
+pub const VMA_LAZY : usize = 2;
+pub const VMA_SHARED : usize = 3;
+
+/// an area of the address space, end is exclusive and kind one of VMA_*
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Vma
+{
+    pub start: usize,
+    pub end: usize,
+    pub prot: usize,
+    pub kind: usize,
+    /// pages with a frame, shared and zero pages included
+    pub resident: usize,
+    pub swapped: usize,
+}
+
+/// the page table entries an address goes through from the root, levels of them are valid
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PageWalk
+{
+    pub levels: usize,
+    pub ptes: [usize; 4],
+    /// 0 if nothing is mapped there
+    pub pa: usize,
+}
+
 fn clean_bss()
 {
     extern "C"
@@ -32,20 +88,77 @@ fn clean_bss()
     )
 }
 
+/// the envp array the program was started with
+static mut ENVIRON : *const *const u8 = core::ptr::null();
+
+// the initial stack is only reachable from sp as the kernel left it
+global_asm!(
+    ".section .text.entry",
+    ".globl _start",
+    "_start:",
+    "    mv a0, sp",
+    "    call start_main",
+);
+
 #[no_mangle]
-#[link_section = ".text.entry"]
-pub fn _start() -> !
+unsafe extern "C" fn start_main(sp: *const usize) -> !
 {
     clean_bss();
-    exit(main());
+    exit(run_main(sp, main));
 }
 
 #[linkage = "weak"]
 #[no_mangle]
-pub fn main() ->isize
+pub fn main(_argc: usize, _argv: &[&str]) -> i32
 {
     panic!("main not found\n");
-    1       
+}
+
+unsafe fn cstr(ptr: *const u8) -> &'static str
+{
+    let mut len = 0;
+    while *ptr.add(len) != 0
+    {
+        len += 1;
+    }
+    core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap_or("")
+}
+
+/// the signature every app declares its main with
+pub type Main = fn(usize, &[&str]) -> i32;
+
+/// call main with the argc and argv found at sp, after which comes envp
+unsafe fn run_main(sp: *const usize, main: Main) -> isize
+{
+    let argc = *sp;
+    let argv = sp.add(1) as *const *const u8;
+    ENVIRON = argv.add(argc + 1);
+    let args: Vec<&'static str> = (0..argc).map(|i| cstr(*argv.add(i))).collect();
+    main(argc, &args) as isize
+}
+
+/// the value of the environment variable name
+pub fn getenv(name: &str) -> Option<&'static str>
+{
+    let mut env = unsafe { ENVIRON };
+    if env.is_null()
+    {
+        return None;
+    }
+    loop
+    {
+        let entry = unsafe { *env };
+        if entry.is_null()
+        {
+            return None;
+        }
+        let entry = unsafe { cstr(entry) };
+        if let Some(value) = entry.strip_prefix(name).and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(value);
+        }
+        env = unsafe { env.add(1) };
+    }
 }
 
 pub fn write(fd:usize, buf:&[u8]) -> isize
@@ -67,3 +180,149 @@ pub fn yield_() -> isize
 {
     sys_yield()
 }
+
+pub fn mmap(addr:usize, len:usize, prot:usize, flags:usize) -> isize
+{
+    sys_mmap(addr, len, prot, flags)
+}
+
+pub fn munmap(addr:usize, len:usize) -> isize
+{
+    sys_munmap(addr, len)
+}
+
+pub fn mprotect(addr:usize, len:usize, prot:usize) -> isize
+{
+    sys_mprotect(addr, len, prot)
+}
+
+pub fn brk(addr:usize) -> isize
+{
+    sys_brk(addr)
+}
+
+/// grow the program break by incr bytes, returns the old break or -1
+pub fn sbrk(incr:isize) -> isize
+{
+    let old = sys_brk(0);
+    let new = (old + incr) as usize;
+    if sys_brk(new) as usize == new
+    {
+        old
+    }
+    else
+    {
+        -1
+    }
+}
+
+/// [soft, hard] limit of resource
+pub fn getrlimit(resource:usize) -> Option<[usize; 2]>
+{
+    let mut rlim = [0; 2];
+    if sys_getrlimit(resource, &mut rlim) == 0
+    {
+        Some(rlim)
+    }
+    else
+    {
+        None
+    }
+}
+
+pub fn setrlimit(resource:usize, soft:usize, hard:usize) -> isize
+{
+    sys_setrlimit(resource, &[soft, hard])
+}
+
+pub fn fork() -> isize
+{
+    sys_fork()
+}
+
+/// replace the program with the app path, started with args and "KEY=VALUE" envs,
+/// only returns if that fails
+pub fn exec(path:&str, args:&[&str], envs:&[&str]) -> isize
+{
+    let cstring = |s: &&str| {
+        let mut string = String::from(*s);
+        string.push('\0');
+        string
+    };
+    let path = cstring(&path);
+    let args: Vec<String> = args.iter().map(cstring).collect();
+    let envs: Vec<String> = envs.iter().map(cstring).collect();
+    let mut argv: Vec<*const u8> = args.iter().map(|arg| arg.as_ptr()).collect();
+    argv.push(core::ptr::null());
+    let mut envp: Vec<*const u8> = envs.iter().map(|env| env.as_ptr()).collect();
+    envp.push(core::ptr::null());
+    sys_exec(path.as_ptr(), argv.as_ptr(), envp.as_ptr())
+}
+
+/// what the kernel returns while the child is still running
+const WAIT_RUNNING : isize = -2;
+
+/// wait for child pid (-1 for any) to exit, yielding until it does,
+/// -1 if there is no such child
+pub fn waitpid(pid:isize, exit_code:&mut isize) -> isize
+{
+    loop
+    {
+        match sys_waitpid(pid, exit_code)
+        {
+            WAIT_RUNNING =>
+            {
+                yield_();
+            }
+            pid => return pid,
+        }
+    }
+}
+
+/// the areas of the address space in address order
+pub fn vmas() -> Vec<Vma>
+{
+    let mut buf = Vec::new();
+    loop
+    {
+        let count = sys_vmas(buf.as_mut_ptr() as *mut u8, buf.len());
+        if count < 0
+        {
+            return Vec::new();
+        }
+        // the space may have changed between the calls, so ask again if it grew
+        if count as usize <= buf.len()
+        {
+            buf.truncate(count as usize);
+            return buf;
+        }
+        buf.resize(count as usize, Vma::default());
+    }
+}
+
+pub fn page_walk(va:usize) -> PageWalk
+{
+    let mut walk = PageWalk::default();
+    sys_pagewalk(va, &mut walk as *mut PageWalk as *mut u8);
+    walk
+}
+
+pub fn shmget(key:usize, size:usize, flags:usize) -> isize
+{
+    sys_shmget(key, size, flags)
+}
+
+pub fn shmat(id:usize, addr:usize, flags:usize) -> isize
+{
+    sys_shmat(id, addr, flags)
+}
+
+pub fn shmdt(addr:usize) -> isize
+{
+    sys_shmdt(addr)
+}
+
+pub fn shmctl(id:usize, cmd:usize) -> isize
+{
+    sys_shmctl(id, cmd)
+}
@@ -3,7 +3,22 @@ use core::arch::asm;
 const WRITE : usize = 64;
 const EXIT : usize = 93;
 const YIELD : usize = 124;
+const GETRLIMIT : usize = 163;
+const SETRLIMIT : usize = 164;
 const GET_TIME : usize = 169;
+const SHMGET : usize = 194;
+const SHMCTL : usize = 195;
+const SHMAT : usize = 196;
+const SHMDT : usize = 197;
+const BRK : usize = 214;
+const MUNMAP : usize = 215;
+const FORK : usize = 220;
+const EXEC : usize = 221;
+const MMAP : usize = 222;
+const MPROTECT : usize = 226;
+const WAITPID : usize = 260;
+const VMAS : usize = 1000;
+const PAGE_WALK : usize = 1001;
 
 
 fn syscall(id:usize, args:[usize; 3]) -> isize
@@ -20,6 +35,23 @@ fn syscall(id:usize, args:[usize; 3]) -> isize
     ret
 }
 
+fn syscall6(id:usize, args:[usize; 6]) -> isize
+{
+    let mut ret : isize;
+    unsafe
+    {
+    asm!("ecall",
+        inlateout("x10") args[0] => ret,
+        in("x11") args[1],
+        in("x12") args[2],
+        in("x13") args[3],
+        in("x14") args[4],
+        in("x15") args[5],
+        in("x17") id);
+    }
+    ret
+}
+
 pub fn sys_write(fd:usize, bufs:&[u8]) -> isize
 {
     let ptr = bufs.as_ptr();
@@ -43,3 +75,78 @@ pub fn sys_gettime() -> usize
     syscall(GET_TIME, [0, 0, 0]) as usize
 }
 
+pub fn sys_mmap(addr:usize, len:usize, prot:usize, flags:usize) -> isize
+{
+    syscall6(MMAP, [addr, len, prot, flags, usize::MAX, 0])
+}
+
+pub fn sys_munmap(addr:usize, len:usize) -> isize
+{
+    syscall(MUNMAP, [addr, len, 0])
+}
+
+pub fn sys_brk(addr:usize) -> isize
+{
+    syscall(BRK, [addr, 0, 0])
+}
+
+pub fn sys_mprotect(addr:usize, len:usize, prot:usize) -> isize
+{
+    syscall(MPROTECT, [addr, len, prot])
+}
+
+pub fn sys_getrlimit(resource:usize, rlim:&mut [usize; 2]) -> isize
+{
+    syscall(GETRLIMIT, [resource, rlim.as_mut_ptr() as usize, 0])
+}
+
+pub fn sys_setrlimit(resource:usize, rlim:&[usize; 2]) -> isize
+{
+    syscall(SETRLIMIT, [resource, rlim.as_ptr() as usize, 0])
+}
+
+pub fn sys_fork() -> isize
+{
+    syscall(FORK, [0, 0, 0])
+}
+
+/// path is nul terminated, argv and envp are null terminated arrays of such strings
+pub fn sys_exec(path:*const u8, argv:*const *const u8, envp:*const *const u8) -> isize
+{
+    syscall(EXEC, [path as usize, argv as usize, envp as usize])
+}
+
+pub fn sys_waitpid(pid:isize, exit_code:&mut isize) -> isize
+{
+    syscall(WAITPID, [pid as usize, exit_code as *mut isize as usize, 0])
+}
+
+pub fn sys_shmget(key:usize, size:usize, flags:usize) -> isize
+{
+    syscall(SHMGET, [key, size, flags])
+}
+
+pub fn sys_shmctl(id:usize, cmd:usize) -> isize
+{
+    syscall(SHMCTL, [id, cmd, 0])
+}
+
+pub fn sys_shmat(id:usize, addr:usize, flags:usize) -> isize
+{
+    syscall(SHMAT, [id, addr, flags])
+}
+
+pub fn sys_shmdt(addr:usize) -> isize
+{
+    syscall(SHMDT, [addr, 0, 0])
+}
+
+pub fn sys_vmas(buf:*mut u8, count:usize) -> isize
+{
+    syscall(VMAS, [buf as usize, count, 0])
+}
+
+pub fn sys_pagewalk(va:usize, buf:*mut u8) -> isize
+{
+    syscall(PAGE_WALK, [va, buf as usize, 0])
+}